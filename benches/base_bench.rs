@@ -1,7 +1,7 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 use rewatch::build;
-use rewatch::package_tree;
 use rewatch::helpers;
+use rewatch::package_tree;
 
 use std::fs::File;
 use std::io::prelude::*;
@@ -13,7 +13,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         let project_root = helpers::get_abs_path(folder);
 
         b.iter(|| {
-            package_tree::make(&project_root);
+            let _ = package_tree::make(&None, &project_root);
         })
     });
 
@@ -22,11 +22,10 @@ fn criterion_benchmark(c: &mut Criterion) {
         let folder = "testrepo";
         let filename = "testrepo/packages/dep02/src/Dep02.res";
         // Clean the build
-        build::clean(folder);
+        let _ = build::clean(folder, true, false);
         // Read the file we'll be mutating
         let mut file = File::options()
             .read(true)
-            .write(true)
             .append(true)
             .open(filename)
             .unwrap();
@@ -35,19 +34,19 @@ fn criterion_benchmark(c: &mut Criterion) {
 
         b.iter(|| {
             // Create initial build
-            let _ = build::build(folder);
+            let _ = build::build(&None, folder, true, false, None, None, None);
             // Update the file
             let _ = writeln!(
                 file,
                 r#"let log2 = () => ["a", "b"]->forEach(Js.log);log2()"#
             );
             // Create another build
-            let _ = build::build(folder);
+            let _ = build::build(&None, folder, true, false, None, None, None);
 
             // Reset state
             File::create(filename).unwrap();
             file.write_all(contents.as_bytes()).unwrap();
-            let _ = build::build(folder);
+            let _ = build::build(&None, folder, true, false, None, None, None);
         })
     });
 }
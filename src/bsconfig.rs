@@ -1,4 +1,7 @@
+use crate::error;
+use crate::helpers;
 use serde::{Deserialize, Serialize};
+use std::hash::{Hash, Hasher};
 use std::{fmt, fs};
 
 #[derive(Deserialize, Debug, Clone)]
@@ -16,12 +19,90 @@ pub enum Subdirs {
 }
 impl Eq for Subdirs {}
 
+/// A `slow-re` filter, the regex is compiled once when the config is read. An invalid regex is
+/// kept as the error, so `read` can report it.
+#[derive(Debug, Clone)]
+pub struct SlowRe {
+    pub source: String,
+    regex: Result<regex::Regex, String>,
+}
+
+impl SlowRe {
+    pub fn new(source: &str) -> SlowRe {
+        SlowRe {
+            source: source.to_owned(),
+            regex: regex::Regex::new(source).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn is_match(&self, file_name: &str) -> bool {
+        self.regex
+            .as_ref()
+            .map(|regex| regex.is_match(file_name))
+            .unwrap_or(false)
+    }
+}
+
+impl<'de> Deserialize<'de> for SlowRe {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|source| SlowRe::new(&source))
+    }
+}
+
+impl PartialEq for SlowRe {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Hash for SlowRe {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.source.hash(state);
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Hash)]
+pub struct FileFilter {
+    /// only the files with a name that matches the regex are included
+    #[serde(rename = "slow-re")]
+    pub slow_re: Option<SlowRe>,
+    pub excludes: Option<Vec<String>>,
+}
+
+/// The files of a source folder, either a list of file names, or a filter on the file names
+#[derive(Deserialize, Debug, Clone, PartialEq, Hash)]
+#[serde(untagged)]
+pub enum Files {
+    Explicit(Vec<String>),
+    Filter(FileFilter),
+}
+
+impl Files {
+    /// Whether a file in the source folder is included
+    pub fn includes(&self, file_name: &str) -> bool {
+        match self {
+            Files::Explicit(files) => files.iter().any(|file| file == file_name),
+            Files::Filter(FileFilter { slow_re, excludes }) => {
+                slow_re
+                    .as_ref()
+                    .map(|slow_re| slow_re.is_match(file_name))
+                    .unwrap_or(true)
+                    && !excludes
+                        .iter()
+                        .flatten()
+                        .any(|exclude| exclude == file_name)
+            }
+        }
+    }
+}
+
 #[derive(Deserialize, Debug, Clone, PartialEq, Hash)]
 pub struct PackageSource {
     pub dir: String,
     pub subdirs: Option<Subdirs>,
     #[serde(rename = "type")]
     pub type_: Option<String>,
+    pub files: Option<Files>,
 }
 
 /// `to_qualified_without_children` takes a tree like structure of dependencies, coming in from
@@ -33,26 +114,39 @@ pub fn to_qualified_without_children(s: &Source) -> PackageSource {
             dir: dir.to_owned(),
             subdirs: None,
             type_: None,
+            files: None,
         },
         Source::Qualified(PackageSource {
             dir,
             type_,
             subdirs: Some(Subdirs::Recurse(should_recurse)),
+            files,
         }) => PackageSource {
             dir: dir.to_owned(),
             subdirs: Some(Subdirs::Recurse(*should_recurse)),
             type_: type_.to_owned(),
+            files: files.to_owned(),
         },
-        Source::Qualified(PackageSource { dir, type_, .. }) => PackageSource {
+        Source::Qualified(PackageSource {
+            dir, type_, files, ..
+        }) => PackageSource {
             dir: dir.to_owned(),
             subdirs: None,
             type_: type_.to_owned(),
+            files: files.to_owned(),
         },
     }
 }
 
 impl Eq for PackageSource {}
 
+impl PackageSource {
+    /// Sources with the type 'dev' are only compiled for the root package and local packages
+    pub fn is_type_dev(&self) -> bool {
+        self.type_.as_deref() == Some("dev")
+    }
+}
+
 #[derive(Deserialize, Debug, Clone, PartialEq, Hash)]
 #[serde(untagged)]
 pub enum Source {
@@ -61,11 +155,73 @@ pub enum Source {
 }
 impl Eq for Source {}
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct PackageSpec {
-    pub module: String,
-    #[serde(rename = "in-source")]
+impl Source {
+    /// The subdirs of a dev source are dev sources as well, unless they have a type themselves
+    pub fn into_type_dev(self) -> Source {
+        match self {
+            Source::Shorthand(dir) => Source::Qualified(PackageSource {
+                dir,
+                subdirs: None,
+                type_: Some("dev".to_string()),
+                files: None,
+            }),
+            Source::Qualified(source) if source.type_.is_none() => {
+                Source::Qualified(PackageSource {
+                    type_: Some("dev".to_string()),
+                    ..source
+                })
+            }
+            source => source,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum ModuleFormat {
+    #[serde(rename = "commonjs")]
+    CommonJs,
+    #[serde(rename = "es6")]
+    Es6,
+    #[serde(rename = "es6-global")]
+    Es6Global,
+}
+
+impl fmt::Display for ModuleFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            serde_json::to_value(self).unwrap().as_str().unwrap()
+        )
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct QualifiedPackageSpec {
+    pub module: ModuleFormat,
+    #[serde(rename = "in-source", default)]
     pub in_source: bool,
+    pub suffix: Option<Suffix>,
+}
+
+impl QualifiedPackageSpec {
+    /// The folder in the package the JavaScript is written to when it's not written next to the
+    /// source files, the stock build system uses the same folders.
+    pub fn get_out_of_source_dir(&self) -> &'static str {
+        match self.module {
+            ModuleFormat::CommonJs => "lib/js",
+            ModuleFormat::Es6 => "lib/es6",
+            ModuleFormat::Es6Global => "lib/es6_global",
+        }
+    }
+}
+
+/// A package spec is either only the module format (`"es6"`), or the qualified object
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum PackageSpec {
+    Shorthand(ModuleFormat),
+    Qualified(QualifiedPackageSpec),
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -108,7 +264,7 @@ pub enum JsxModule {
     React,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub enum Suffix {
     #[serde(rename = ".js")]
     Js,
@@ -149,6 +305,8 @@ pub struct JsxSpecs {
 pub struct T {
     pub name: String,
     pub sources: OneOrMore<Source>,
+    #[serde(rename = "ignored-dirs")]
+    pub ignored_dirs: Option<Vec<String>>,
     #[serde(rename = "package-specs")]
     pub package_specs: Option<OneOrMore<PackageSpec>>,
     pub warnings: Option<Warnings>,
@@ -178,53 +336,93 @@ pub fn flatten_flags(flags: &Option<Vec<OneOrMore<String>>>) -> Vec<String> {
         None => vec![],
         Some(xs) => xs
             .iter()
-            .map(|x| match x {
+            .flat_map(|x| match x {
                 OneOrMore::Single(y) => vec![y.to_owned()],
                 OneOrMore::Multiple(ys) => ys.to_owned(),
             })
-            .flatten()
             .collect::<Vec<String>>()
             .iter()
-            .map(|str| str.split(" "))
-            .flatten()
+            .flat_map(|str| str.split(" "))
             .map(|str| str.to_string())
             .collect::<Vec<String>>(),
     }
 }
 
+/// The package specs of a bsconfig, every spec is a JavaScript file that is generated for every
+/// module. Without package specs, rewatch generates ES6 next to the source files.
+pub fn get_package_specs(bsconfig: &T) -> Vec<QualifiedPackageSpec> {
+    let package_specs = match bsconfig.package_specs.to_owned() {
+        None => vec![],
+        Some(OneOrMore::Single(package_spec)) => vec![package_spec],
+        Some(OneOrMore::Multiple(package_specs)) => package_specs,
+    };
+    if package_specs.is_empty() {
+        return vec![QualifiedPackageSpec {
+            module: ModuleFormat::Es6,
+            in_source: true,
+            suffix: None,
+        }];
+    }
+    package_specs
+        .into_iter()
+        .map(|package_spec| match package_spec {
+            PackageSpec::Shorthand(module) => QualifiedPackageSpec {
+                module,
+                in_source: false,
+                suffix: None,
+            },
+            PackageSpec::Qualified(package_spec) => package_spec,
+        })
+        .collect()
+}
+
+/// The suffix of the JavaScript files of a package spec. When the spec doesn't have one, it's the
+/// suffix of the package, and then the one of the root package.
+pub fn get_suffix(package_spec: &QualifiedPackageSpec, bsconfig: &T, root_bsconfig: &T) -> Suffix {
+    package_spec
+        .suffix
+        .as_ref()
+        .or(bsconfig.suffix.as_ref())
+        .or(root_bsconfig.suffix.as_ref())
+        .cloned()
+        .unwrap_or(Suffix::Mjs)
+}
+
+/// A ppx is either relative to the package (`./ppx`), or a file in another package
+/// (`some-ppx/ppx`, `@scope/some-ppx/ppx`), that is found like the dependencies of the package.
+fn get_ppx_path(ppx: &str, package_dir: &str) -> String {
+    if ppx.starts_with('.') {
+        return package_dir.to_owned() + "/" + ppx;
+    }
+    let name_segments = if ppx.starts_with('@') { 2 } else { 1 };
+    let mut segments = ppx.splitn(name_segments + 1, '/');
+    let package_name = segments
+        .by_ref()
+        .take(name_segments)
+        .collect::<Vec<&str>>()
+        .join("/");
+    match (
+        helpers::resolve_package(&package_name, package_dir),
+        segments.next(),
+    ) {
+        (Some(ppx_package_dir), Some(file)) => ppx_package_dir + "/" + file,
+        (Some(ppx_package_dir), None) => ppx_package_dir,
+        (None, _) => package_dir.to_owned() + "/node_modules/" + ppx,
+    }
+}
+
 /// Since ppx-flags could be one or more, and could be nested potentiall, this function takes the
 /// flags and flattens them outright.
-pub fn flatten_ppx_flags(
-    node_modules_dir: &String,
-    flags: &Option<Vec<OneOrMore<String>>>,
-    package_name: &String,
-) -> Vec<String> {
+pub fn flatten_ppx_flags(package_dir: &str, flags: &Option<Vec<OneOrMore<String>>>) -> Vec<String> {
     match flags {
         None => vec![],
         Some(xs) => xs
             .iter()
-            .map(|x| match x {
-                OneOrMore::Single(y) => {
-                    let first_character = y.chars().nth(0);
-                    match first_character {
-                        Some('.') => {
-                            vec![
-                                "-ppx".to_string(),
-                                node_modules_dir.to_owned() + "/" + package_name + "/" + y,
-                            ]
-                        }
-                        _ => vec!["-ppx".to_string(), node_modules_dir.to_owned() + "/" + y],
-                    }
-                }
-                OneOrMore::Multiple(ys) if ys.len() == 0 => vec![],
+            .flat_map(|x| match x {
+                OneOrMore::Single(y) => vec!["-ppx".to_string(), get_ppx_path(y, package_dir)],
+                OneOrMore::Multiple(ys) if ys.is_empty() => vec![],
                 OneOrMore::Multiple(ys) => {
-                    let first_character = ys[0].chars().nth(0);
-                    let ppx = match first_character {
-                        Some('.') => {
-                            node_modules_dir.to_owned() + "/" + package_name + "/" + &ys[0]
-                        }
-                        _ => node_modules_dir.to_owned() + "/" + &ys[0],
-                    };
+                    let ppx = get_ppx_path(&ys[0], package_dir);
                     vec![
                         "-ppx".to_string(),
                         vec![ppx]
@@ -235,18 +433,227 @@ pub fn flatten_ppx_flags(
                     ]
                 }
             })
-            .flatten()
             .collect::<Vec<String>>(),
     }
 }
 
+/// The names of the config file of a package, newer packages have a `rescript.json`, which is
+/// preferred when a package has both.
+pub const CONFIG_FILES: [&str; 2] = ["rescript.json", "bsconfig.json"];
+
+/// Whether a file name is the name of a config file
+pub fn is_config_file(file_name: &str) -> bool {
+    CONFIG_FILES.contains(&file_name)
+}
+
+/// The path of the config file of the package in `package_dir`, if it has one
+pub fn get_config_path(package_dir: &str) -> Option<String> {
+    CONFIG_FILES
+        .iter()
+        .map(|file_name| format!("{}/{}", package_dir, file_name))
+        .find(|path| std::path::Path::new(path).exists())
+}
+
+/// Reads the config of the package in `package_dir`, from its `rescript.json` or `bsconfig.json`
+pub fn read_package_config(package_dir: &str) -> Result<T, error::Error> {
+    match get_config_path(package_dir) {
+        Some(path) => read(path),
+        None => Err(error::Error::Config {
+            path: package_dir.to_string(),
+            message: format!("there is no {}", CONFIG_FILES.join(" or ")),
+        }),
+    }
+}
+
 /// Try to convert a bsconfig from a certain path to a bsconfig struct
-pub fn read(path: String) -> T {
-    fs::read_to_string(path.clone())
-        .map_err(|e| format!("Could not read bsconfig. {path} - {e}"))
-        .and_then(|x| {
-            serde_json::from_str::<T>(&x)
-                .map_err(|e| format!("Could not parse bsconfig. {path} - {e}"))
+pub fn read(path: String) -> Result<T, error::Error> {
+    let contents = fs::read_to_string(&path).map_err(|e| error::Error::Config {
+        path: path.to_owned(),
+        message: e.to_string(),
+    })?;
+    let bsconfig = serde_json::from_str::<T>(&contents).map_err(|e| error::Error::Config {
+        path: path.to_owned(),
+        message: e.to_string(),
+    })?;
+    let sources = match &bsconfig.sources {
+        OneOrMore::Single(source) => vec![source],
+        OneOrMore::Multiple(sources) => sources.iter().collect(),
+    };
+    if let Some(e) = get_slow_res(sources)
+        .into_iter()
+        .find_map(|slow_re| slow_re.regex.as_ref().err())
+    {
+        return Err(error::Error::Config {
+            path,
+            message: format!("Invalid slow-re: {}", e),
+        });
+    }
+    match bsconfig.jsx.as_ref().and_then(|jsx| jsx.version) {
+        Some(version) if version != 3 && version != 4 => Err(error::Error::Config {
+            path,
+            message: format!("Unsupported JSX version {}, use 3 or 4", version),
+        }),
+        _ => Ok(bsconfig),
+    }
+}
+
+/// All the `slow-re` filters of the sources and their subdirs
+fn get_slow_res(sources: Vec<&Source>) -> Vec<&SlowRe> {
+    sources
+        .into_iter()
+        .flat_map(|source| match source {
+            Source::Shorthand(_) => vec![],
+            Source::Qualified(PackageSource { files, subdirs, .. }) => {
+                let mut slow_res = match files {
+                    Some(Files::Filter(FileFilter {
+                        slow_re: Some(slow_re),
+                        ..
+                    })) => vec![slow_re],
+                    _ => vec![],
+                };
+                if let Some(Subdirs::Qualified(subdirs)) = subdirs {
+                    slow_res.extend(get_slow_res(subdirs.iter().collect()));
+                }
+                slow_res
+            }
         })
-        .expect("Errors reading bsconfig")
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bsconfig(fields: &str) -> T {
+        serde_json::from_str(&format!(
+            r#"{{"name": "test", "sources": "src"{}}}"#,
+            fields
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_get_package_specs() {
+        // without package-specs, bsc writes ES6 next to the sources
+        assert_eq!(
+            get_package_specs(&bsconfig("")),
+            vec![QualifiedPackageSpec {
+                module: ModuleFormat::Es6,
+                in_source: true,
+                suffix: None,
+            }]
+        );
+        assert_eq!(
+            get_package_specs(&bsconfig(r#", "package-specs": []"#)),
+            get_package_specs(&bsconfig(""))
+        );
+        // the shorthand is written out of source, like the qualified spec without in-source
+        assert_eq!(
+            get_package_specs(&bsconfig(
+                r#", "package-specs": ["commonjs", {"module": "es6-global"}, {"module": "es6", "in-source": true, "suffix": ".bs.js"}]"#
+            )),
+            vec![
+                QualifiedPackageSpec {
+                    module: ModuleFormat::CommonJs,
+                    in_source: false,
+                    suffix: None,
+                },
+                QualifiedPackageSpec {
+                    module: ModuleFormat::Es6Global,
+                    in_source: false,
+                    suffix: None,
+                },
+                QualifiedPackageSpec {
+                    module: ModuleFormat::Es6,
+                    in_source: true,
+                    suffix: Some(Suffix::BsJs),
+                },
+            ]
+        );
+        assert_eq!(
+            get_package_specs(&bsconfig(r#", "package-specs": {"module": "commonjs"}"#)),
+            vec![QualifiedPackageSpec {
+                module: ModuleFormat::CommonJs,
+                in_source: false,
+                suffix: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_get_suffix() {
+        let package_spec = |suffix: Option<Suffix>| QualifiedPackageSpec {
+            module: ModuleFormat::Es6,
+            in_source: true,
+            suffix,
+        };
+        let with_suffix = bsconfig(r#", "suffix": ".bs.js""#);
+        let with_other_suffix = bsconfig(r#", "suffix": ".cjs""#);
+        let without_suffix = bsconfig("");
+
+        // the suffix of the spec comes first
+        assert_eq!(
+            get_suffix(
+                &package_spec(Some(Suffix::Js)),
+                &with_suffix,
+                &with_other_suffix
+            ),
+            Suffix::Js
+        );
+        // then the one of the package
+        assert_eq!(
+            get_suffix(&package_spec(None), &with_suffix, &with_other_suffix),
+            Suffix::BsJs
+        );
+        // then the one of the root package
+        assert_eq!(
+            get_suffix(&package_spec(None), &without_suffix, &with_other_suffix),
+            Suffix::Cjs
+        );
+        // and otherwise .mjs
+        assert_eq!(
+            get_suffix(&package_spec(None), &without_suffix, &without_suffix),
+            Suffix::Mjs
+        );
+    }
+
+    #[test]
+    fn test_files_includes() {
+        let files = |json: &str| serde_json::from_str::<Files>(json).unwrap();
+
+        let explicit = files(r#"["App.res", "Button.res"]"#);
+        assert!(explicit.includes("App.res"));
+        assert!(!explicit.includes("Other.res"));
+
+        let slow_re = files(r#"{"slow-re": "^[A-Z].*\\.res$"}"#);
+        assert!(slow_re.includes("App.res"));
+        assert!(!slow_re.includes("app.res"));
+        assert!(!slow_re.includes("App.resi"));
+
+        let excludes = files(r#"{"excludes": ["Skipped.res"]}"#);
+        assert!(excludes.includes("App.res"));
+        assert!(!excludes.includes("Skipped.res"));
+
+        let both = files(r#"{"slow-re": "^[A-Z]", "excludes": ["Skipped.res"]}"#);
+        assert!(both.includes("App.res"));
+        assert!(!both.includes("app.res"));
+        assert!(!both.includes("Skipped.res"));
+    }
+
+    #[test]
+    fn test_read_reports_an_invalid_slow_re() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("bsconfig.json");
+        fs::write(
+            &path,
+            r#"{"name": "test", "sources": {"dir": "src", "files": {"slow-re": "("}}}"#,
+        )
+        .unwrap();
+        match read(path.to_string_lossy().to_string()) {
+            Err(error::Error::Config { message, .. }) => {
+                assert!(message.starts_with("Invalid slow-re"), "{}", message)
+            }
+            result => panic!("expected a config error, got {:?}", result.map(|_| ())),
+        }
+    }
 }
@@ -2,7 +2,8 @@ use crate::bsconfig;
 use crate::bsconfig::OneOrMore;
 use crate::build_types::*;
 use crate::clean;
-use crate::clean::clean_mjs_files;
+use crate::clean::clean_js_files;
+use crate::error::{DependencyStep, Error};
 use crate::helpers;
 use crate::helpers::emojis::*;
 use crate::helpers::is_interface_ast_file;
@@ -10,32 +11,57 @@ use crate::logs;
 use crate::package_tree;
 use ahash::AHashSet;
 use console::style;
+use diagnostics::Severity;
 use indicatif::{ProgressBar, ProgressStyle};
-use log::{debug, error, info, log_enabled, Level::Info};
+use log::{debug, log_enabled, Level::Info};
 use rayon::prelude::*;
-use std::fs::{self, File};
+use std::fs::File;
 use std::io::{stdout, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::time::Instant;
-
-pub fn get_interface<'a>(module: &'a Module) -> &'a Option<Interface> {
+use std::time::{Duration, Instant};
+
+pub mod cache;
+pub mod cancel;
+pub mod cycles;
+pub mod diagnostics;
+pub mod report;
+mod sarif;
+pub mod scheduler;
+pub mod state;
+mod timings;
+
+pub fn get_interface(module: &Module) -> &Option<Interface> {
     match &module.source_type {
         SourceType::SourceFile(source_file) => &source_file.interface,
         _ => &None,
     }
 }
 
-pub fn get_version(project_root: &str) -> String {
-    let version_cmd = Command::new(helpers::get_bsc(&project_root))
+pub fn get_version(project_root: &str) -> Result<String, Error> {
+    let bsc = helpers::get_bsc(project_root)?;
+    let version_cmd = Command::new(&bsc)
         .args(["-v"])
         .output()
-        .expect("failed to find version");
+        .map_err(|e| Error::Compiler {
+            message: format!("Could not run the ReScript compiler ({}): {}", bsc, e),
+        })?;
 
     std::str::from_utf8(&version_cmd.stdout)
-        .expect("Could not read version from rescript")
-        .replace("\n", "")
-        .replace("ReScript ", "")
+        .ok()
+        .map(|version| version.replace('\n', "").replace("ReScript ", ""))
+        .filter(|version| get_major_version(version).is_some())
+        .ok_or_else(|| Error::Compiler {
+            message: format!(
+                "Could not read the version of the ReScript compiler ({}): {}",
+                bsc,
+                String::from_utf8_lossy(&version_cmd.stdout).trim()
+            ),
+        })
+}
+
+fn get_major_version(version: &str) -> Option<usize> {
+    version.split('.').next()?.parse::<usize>().ok()
 }
 
 fn get_jsx_args(package: &package_tree::Package) -> Vec<String> {
@@ -43,11 +69,9 @@ fn get_jsx_args(package: &package_tree::Package) -> Vec<String> {
         package.bsconfig.reason.to_owned(),
         package.bsconfig.jsx.to_owned(),
     ) {
+        // the version is checked when the config is read
         (_, Some(jsx)) => match jsx.version {
-            Some(version) if version == 3 || version == 4 => {
-                vec!["-bs-jsx".to_string(), version.to_string()]
-            }
-            Some(_version) => panic!("Unsupported JSX version"),
+            Some(version) => vec!["-bs-jsx".to_string(), version.to_string()],
             None => vec![],
         },
         (Some(reason), None) => {
@@ -85,8 +109,9 @@ fn get_jsx_module_args(package: &package_tree::Package) -> Vec<String> {
     }
 }
 
+/// The version comes from `get_version`, which checks that it has a major version
 fn check_if_rescript11_or_higher(version: &str) -> bool {
-    version.split(".").nth(0).unwrap().parse::<usize>().unwrap() >= 11
+    get_major_version(version).is_some_and(|major| major >= 11)
 }
 
 fn get_uncurried_args(
@@ -115,28 +140,73 @@ fn get_uncurried_args(
 
 fn filter_ppx_flags(ppx_flags: &Option<Vec<OneOrMore<String>>>) -> Option<Vec<OneOrMore<String>>> {
     let filter = "bisect";
-    match ppx_flags {
-        Some(flags) => Some(
-            flags
-                .iter()
-                .filter(|flag| match flag {
-                    bsconfig::OneOrMore::Single(str) => !str.contains(filter),
-                    bsconfig::OneOrMore::Multiple(str) => !str.first().unwrap().contains(filter),
-                })
-                .map(|x| x.to_owned())
-                .collect::<Vec<OneOrMore<String>>>(),
-        ),
-        None => None,
+    ppx_flags.as_ref().map(|flags| {
+        flags
+            .iter()
+            .filter(|flag| match flag {
+                bsconfig::OneOrMore::Single(str) => !str.contains(filter),
+                bsconfig::OneOrMore::Multiple(str) => !str.first().unwrap().contains(filter),
+            })
+            .map(|x| x.to_owned())
+            .collect::<Vec<OneOrMore<String>>>()
+    })
+}
+
+fn get_warning_args(root_package: &package_tree::Package) -> Vec<String> {
+    match root_package.bsconfig.warnings.to_owned() {
+        None => vec![],
+        Some(warnings) => {
+            let warn_number = match warnings.number {
+                None => vec![],
+                Some(warnings) => {
+                    vec!["-w".to_string(), warnings.to_string()]
+                }
+            };
+
+            let warn_error = match warnings.error {
+                Some(bsconfig::Error::Catchall(true)) => {
+                    vec!["-warn-error".to_string(), "A".to_string()]
+                }
+                Some(bsconfig::Error::Qualified(errors)) => {
+                    vec!["-warn-error".to_string(), errors.to_string()]
+                }
+                _ => vec![],
+            };
+
+            [warn_number, warn_error].concat()
+        }
     }
 }
 
 fn path_to_ast_extension(path: &Path) -> &str {
     let extension = path.extension().unwrap().to_str().unwrap();
-    return if is_interface_ast_file(extension) {
+    if is_interface_ast_file(extension) {
         ".iast"
     } else {
         ".ast"
-    };
+    }
+}
+
+/// All the arguments that influence the AST of a source file in a package, apart from the
+/// source itself.
+pub fn get_parse_args(
+    version: &str,
+    package: &package_tree::Package,
+    root_package: &package_tree::Package,
+) -> Vec<String> {
+    [
+        vec!["-bs-v".to_string(), version.to_string()],
+        bsconfig::flatten_ppx_flags(
+            &package.package_dir,
+            &filter_ppx_flags(&package.bsconfig.ppx_flags),
+        ),
+        get_jsx_args(root_package),
+        get_jsx_module_args(root_package),
+        get_jsx_mode_args(root_package),
+        get_uncurried_args(version, package, root_package),
+        bsconfig::flatten_flags(&package.bsconfig.bsc_flags),
+    ]
+    .concat()
 }
 
 fn generate_ast(
@@ -147,34 +217,16 @@ fn generate_ast(
     version: &str,
 ) -> Result<(String, Option<String>), String> {
     let file = &filename.to_string();
-    let build_path_abs = helpers::get_build_path(root_path, &package.name);
+    let build_path_abs = package.get_build_path();
     let path = PathBuf::from(filename);
     let ast_extension = path_to_ast_extension(&path);
 
     let ast_path = (helpers::get_basename(&file.to_string()).to_owned()) + ast_extension;
-    let abs_node_modules_path = helpers::get_node_modules_path(root_path);
-
-    let ppx_flags = bsconfig::flatten_ppx_flags(
-        &abs_node_modules_path,
-        &filter_ppx_flags(&package.bsconfig.ppx_flags),
-        &package.name,
-    );
-
-    let jsx_args = get_jsx_args(&root_package);
-    let jsx_module_args = get_jsx_module_args(&root_package);
-    let jsx_mode_args = get_jsx_mode_args(&root_package);
-    let uncurried_args = get_uncurried_args(version, &package, &root_package);
-    let bsc_flags = bsconfig::flatten_flags(&package.bsconfig.bsc_flags);
+    let parse_args = get_parse_args(version, &package, &root_package);
 
     let res_to_ast_args = |file: String| -> Vec<String> {
-        vec![
-            vec!["-bs-v".to_string(), format!("{}", version)],
-            ppx_flags,
-            jsx_args,
-            jsx_module_args,
-            jsx_mode_args,
-            uncurried_args,
-            bsc_flags,
+        [
+            parse_args,
             vec![
                 "-absname".to_string(),
                 "-bs-ast".to_string(),
@@ -186,14 +238,16 @@ fn generate_ast(
         .concat()
     };
 
+    let bsc = helpers::get_bsc(root_path).map_err(|e| e.to_string())?;
     /* Create .ast */
     if let Some(res_to_ast) = helpers::canonicalize_string_path(file).map(|file| {
-        Command::new(helpers::get_bsc(&root_path))
+        Command::new(&bsc)
             .current_dir(helpers::canonicalize_string_path(&build_path_abs).unwrap())
             .args(res_to_ast_args(file))
             .output()
-            .expect("Error converting .res to .ast")
     }) {
+        let res_to_ast =
+            res_to_ast.map_err(|e| format!("Could not run bsc to parse {}: {}\n", filename, e))?;
         let stderr = std::str::from_utf8(&res_to_ast.stderr).expect("Expect StdErr to be non-null");
         if helpers::contains_ascii_characters(stderr) {
             if res_to_ast.status.success() {
@@ -205,10 +259,10 @@ fn generate_ast(
             Ok((ast_path, None))
         }
     } else {
-        return Err(format!(
+        Err(format!(
             "Could not find canonicalize_string_path for file {} in package {}",
             file, package.name
-        ));
+        ))
     }
 }
 
@@ -231,31 +285,26 @@ fn get_dep_modules(
     namespace: Option<String>,
     package_modules: &AHashSet<String>,
     valid_modules: &AHashSet<String>,
-) -> AHashSet<String> {
+) -> Result<AHashSet<String>, Error> {
     let mut deps = AHashSet::new();
-    if let Ok(lines) = helpers::read_lines(ast_file.to_string()) {
-        // we skip the first line with is some null characters
-        // the following lines in the AST are the dependency modules
-        // we stop when we hit a line that starts with a "/", this is the path of the file.
-        // this is the point where the dependencies end and the actual AST starts
-        for line in lines.skip(1) {
-            match line {
-                Ok(line) => {
-                    let line = line.trim().to_string();
-                    if line.starts_with('/') {
-                        break;
-                    } else if !line.is_empty() {
-                        deps.insert(line);
-                    }
-                }
-                Err(_) => (),
-            }
+    let lines = helpers::read_lines(ast_file.to_string()).map_err(|error| Error::Io {
+        context: format!("Could not read the AST {}", ast_file),
+        error,
+    })?;
+    // we skip the first line with is some null characters
+    // the following lines in the AST are the dependency modules
+    // we stop when we hit a line that starts with a "/", this is the path of the file.
+    // this is the point where the dependencies end and the actual AST starts
+    for line in lines.skip(1).flatten() {
+        let line = line.trim().to_string();
+        if line.starts_with('/') {
+            break;
+        } else if !line.is_empty() {
+            deps.insert(line);
         }
-    } else {
-        panic!("Could not read file {}", ast_file);
     }
 
-    return deps
+    Ok(deps
         .iter()
         .map(|dep| {
             let dep_first = dep.split('.').next().unwrap();
@@ -269,12 +318,12 @@ fn get_dep_modules(
                         Some(dep_second) if dep_first == namespace => dep_second,
                         _ => dep_first,
                     };
-                    let namespaced_name = dep.to_owned() + "-" + &namespace;
+                    let namespaced_name = dep.to_owned() + "-" + namespace;
                     if package_modules.contains(&namespaced_name) {
-                        return namespaced_name;
+                        namespaced_name
                     } else {
-                        return dep.to_string();
-                    };
+                        dep.to_string()
+                    }
                 }
                 None => dep_first.to_string(),
             }
@@ -286,16 +335,15 @@ fn get_dep_modules(
                     None => true,
                 }
         })
-        .collect::<AHashSet<String>>();
+        .collect::<AHashSet<String>>())
 }
 
 fn gen_mlmap(
     package: &package_tree::Package,
     namespace: &str,
     depending_modules: AHashSet<String>,
-    root_path: &str,
 ) -> String {
-    let build_path_abs = helpers::get_build_path(root_path, &package.name);
+    let build_path_abs = package.get_build_path();
     // we don't really need to create a digest, because we track if we need to
     // recompile in a different way but we need to put it in the file for it to
     // be readable.
@@ -316,6 +364,19 @@ fn gen_mlmap(
     path.to_string()
 }
 
+/// The output of bsc when parsing a file, with the severity it should be reported with. Warnings
+/// are only reported for pinned dependencies.
+fn get_parse_output(
+    result: Result<Option<String>, String>,
+    is_pinned_dep: bool,
+) -> Option<(String, Severity)> {
+    match result {
+        Ok(Some(err)) if is_pinned_dep => Some((err, Severity::Warning)),
+        Ok(_) => None,
+        Err(err) => Some((err, Severity::Error)),
+    }
+}
+
 fn generate_asts(
     version: &str,
     build_state: &mut BuildState,
@@ -338,31 +399,34 @@ fn generate_asts(
                     // probably better to do this in a different function
                     // specific to compiling mlmaps
                     let path = helpers::get_mlmap_path(
-                        &build_state.project_root,
-                        &module.package_name,
+                        package,
                         &package
                             .namespace
                             .to_suffix()
                             .expect("namespace should be set for mlmap module"),
                     );
                     let compile_path = helpers::get_mlmap_compile_path(
-                        &build_state.project_root,
-                        &module.package_name,
+                        package,
                         &package
                             .namespace
                             .to_suffix()
                             .expect("namespace should be set for mlmap module"),
                     );
-                    let mlmap_hash = compute_file_hash(&compile_path);
-                    compile_mlmap(&package, module_name, &build_state.project_root);
-                    let mlmap_hash_after = compute_file_hash(&compile_path);
+                    let mlmap_hash = helpers::compute_file_hash(&compile_path);
+                    let result = compile_mlmap(package, module_name, &build_state.project_root);
+                    let mlmap_hash_after = helpers::compute_file_hash(&compile_path);
 
                     let is_dirty = match (mlmap_hash, mlmap_hash_after) {
                         (Some(digest), Some(digest_after)) => !digest.eq(&digest_after),
                         _ => true,
                     };
 
-                    (module_name.to_owned(), Ok((path, None)), Ok(None), is_dirty)
+                    (
+                        module_name.to_owned(),
+                        result.map(|_| (path, None)),
+                        Ok(None),
+                        is_dirty,
+                    )
                 }
 
                 SourceType::SourceFile(source_file) => {
@@ -370,20 +434,20 @@ fn generate_asts(
                         .get_package(&build_state.root_config_name)
                         .unwrap();
 
-                    let (ast_path, iast_path) = if source_file.implementation.dirty
+                    let is_dirty = source_file.implementation.dirty
                         || source_file
                             .interface
                             .as_ref()
                             .map(|i| i.dirty)
-                            .unwrap_or(false)
-                    {
+                            .unwrap_or(false);
+                    let (ast_path, iast_path) = if is_dirty {
                         pb.inc(1);
                         let ast_result = generate_ast(
                             package.to_owned(),
                             root_package.to_owned(),
                             &source_file.implementation.path.to_owned(),
                             &build_state.project_root,
-                            &version,
+                            version,
                         );
 
                         let iast_result =
@@ -393,9 +457,9 @@ fn generate_asts(
                                     root_package.to_owned(),
                                     &interface_file_path.to_owned(),
                                     &build_state.project_root,
-                                    &version,
+                                    version,
                                 )
-                                .map(|result| Some(result)),
+                                .map(Some),
                                 _ => Ok(None),
                             };
 
@@ -413,7 +477,7 @@ fn generate_asts(
                         )
                     };
 
-                    (module_name.to_owned(), ast_path, iast_path, true)
+                    (module_name.to_owned(), ast_path, iast_path, is_dirty)
                 }
             }
         })
@@ -433,73 +497,74 @@ fn generate_asts(
                     .get(&module.package_name)
                     .expect("Package not found");
                 if is_dirty {
-                    match module.source_type {
-                        SourceType::MlMap(_) => module.compile_dirty = true,
-                        _ => (),
+                    if let SourceType::MlMap(_) = module.source_type {
+                        module.compile_dirty = true
                     }
                 }
-                match ast_path {
-                    Ok((_path, err)) => {
-                        // supress warnings in non-pinned deps
-                        if package.is_pinned_dep {
-                            if let Some(err) = err {
-                                match module.source_type {
-                                    SourceType::SourceFile(ref mut source_file) => {
-                                        source_file.implementation.parse_state =
-                                            ParseState::Warning;
-                                    }
-                                    _ => (),
-                                }
-                                logs::append(&package.package_dir, &err);
-                                stderr.push_str(&err);
-                            }
-                        }
-                    }
-                    Err(err) => {
-                        match module.source_type {
-                            SourceType::SourceFile(ref mut source_file) => {
-                                source_file.implementation.parse_state = ParseState::ParseError;
-                            }
-                            _ => (),
-                        }
-                        logs::append(&package.package_dir, &err);
+                // supress warnings in non-pinned deps
+                let implementation_output =
+                    get_parse_output(ast_path.map(|(_path, err)| err), package.is_pinned_dep);
+                let interface_output = get_parse_output(
+                    iast_path.map(|iast| iast.and_then(|(_path, err)| err)),
+                    package.is_pinned_dep,
+                );
+                let mut report = |output: &str, file: &str, severity: Severity| {
+                    let parse_diagnostics = diagnostics::parse(output, file, severity);
+                    let rendered = diagnostics::render(&parse_diagnostics);
+                    logs::append(&package.package_dir, &rendered);
+                    stderr.push_str(&rendered);
+                    if severity == Severity::Error {
                         has_failure = true;
-                        stderr.push_str(&err);
                     }
+                    parse_diagnostics
                 };
-                match iast_path {
-                    Ok(Some((_path, err))) => {
-                        // supress warnings in non-pinned deps
-                        if package.is_pinned_dep {
-                            if let Some(err) = err {
-                                match module.source_type {
-                                    SourceType::SourceFile(ref mut source_file) => {
-                                        source_file.interface.as_mut().map(|interface| {
-                                            interface.parse_state = ParseState::ParseError
-                                        });
-                                    }
-                                    _ => (),
-                                }
-                                logs::append(&package.package_dir, &err);
-                                stderr.push_str(&err);
-                            }
+                match module.source_type {
+                    SourceType::MlMap(_) => {
+                        if let Some((err, severity)) = implementation_output {
+                            report(&err, &module_name, severity);
                         }
                     }
-                    Ok(None) => (),
-                    Err(err) => {
-                        match module.source_type {
-                            SourceType::SourceFile(ref mut source_file) => {
-                                source_file.interface.as_mut().map(|interface| {
-                                    interface.parse_state = ParseState::ParseError
-                                });
+                    SourceType::SourceFile(ref mut source_file) => {
+                        // the warnings of the previous parse are gone when it parses cleanly now
+                        if is_dirty {
+                            source_file.implementation.parse_state = ParseState::Success;
+                            source_file.implementation.parse_diagnostics = vec![];
+                            if let Some(interface) = source_file.interface.as_mut() {
+                                interface.parse_state = ParseState::Success;
+                                interface.parse_diagnostics = vec![];
                             }
-                            _ => (),
                         }
-                        logs::append(&package.package_dir, &err);
-                        has_failure = true;
-                        stderr.push_str(&err);
+                        if let Some((err, severity)) = implementation_output {
+                            let implementation = &mut source_file.implementation;
+                            implementation.parse_state = match severity {
+                                Severity::Error => ParseState::ParseError,
+                                Severity::Warning => ParseState::Warning,
+                            };
+                            implementation.parse_diagnostics =
+                                report(&err, &implementation.path, severity);
+                        }
+                        if let (Some((err, severity)), Some(interface)) =
+                            (interface_output, source_file.interface.as_mut())
+                        {
+                            interface.parse_state = match severity {
+                                Severity::Error => ParseState::ParseError,
+                                Severity::Warning => ParseState::Warning,
+                            };
+                            interface.parse_diagnostics = report(&err, &interface.path, severity);
+                        }
                     }
-                };
+                }
+                // the sources that parsed have an up to date AST now
+                if let SourceType::SourceFile(ref mut source_file) = module.source_type {
+                    if source_file.implementation.parse_state != ParseState::ParseError {
+                        source_file.implementation.dirty = false;
+                    }
+                    if let Some(interface) = source_file.interface.as_mut() {
+                        if interface.parse_state != ParseState::ParseError {
+                            interface.dirty = false;
+                        }
+                    }
+                }
             }
         });
 
@@ -510,7 +575,7 @@ fn generate_asts(
     }
 }
 
-fn get_deps(build_state: &mut BuildState, deleted_modules: &AHashSet<String>) {
+fn get_deps(build_state: &mut BuildState, deleted_modules: &AHashSet<String>) -> Result<(), Error> {
     let all_mod = &build_state
         .module_names
         .union(deleted_modules)
@@ -519,41 +584,31 @@ fn get_deps(build_state: &mut BuildState, deleted_modules: &AHashSet<String>) {
     build_state
         .modules
         .par_iter()
+        .filter(|(_, module)| module.deps_dirty)
         .map(|(module_name, module)| match &module.source_type {
-            SourceType::MlMap(_) => (module_name.to_string(), module.deps.to_owned()),
+            SourceType::MlMap(_) => Ok((module_name.to_string(), module.deps.to_owned())),
             SourceType::SourceFile(source_file) => {
                 let package = build_state
                     .get_package(&module.package_name)
                     .expect("Package not found");
-                let ast_path = helpers::get_ast_path(
-                    &source_file.implementation.path,
-                    &module.package_name,
-                    &build_state.project_root,
-                );
+                let ast_path = helpers::get_ast_path(&source_file.implementation.path, package);
 
                 let mut deps = get_dep_modules(
                     &ast_path,
                     package.namespace.to_suffix(),
-                    &package.modules.as_ref().unwrap(),
+                    package.modules.as_ref().unwrap(),
                     all_mod,
-                );
+                )?;
 
-                match &source_file.interface {
-                    Some(interface) => {
-                        let iast_path = helpers::get_iast_path(
-                            &interface.path,
-                            &module.package_name,
-                            &build_state.project_root,
-                        );
+                if let Some(interface) = &source_file.interface {
+                    let iast_path = helpers::get_iast_path(&interface.path, package);
 
-                        deps.extend(get_dep_modules(
-                            &iast_path,
-                            package.namespace.to_suffix(),
-                            &package.modules.as_ref().unwrap(),
-                            all_mod,
-                        ))
-                    }
-                    None => (),
+                    deps.extend(get_dep_modules(
+                        &iast_path,
+                        package.namespace.to_suffix(),
+                        package.modules.as_ref().unwrap(),
+                        all_mod,
+                    )?)
                 }
                 match &package.namespace {
                     package_tree::Namespace::NamespaceWithEntry {
@@ -565,209 +620,349 @@ fn get_deps(build_state: &mut BuildState, deleted_modules: &AHashSet<String>) {
                     _ => (),
                 }
                 deps.remove(module_name);
-                (module_name.to_string(), deps)
+                Ok((module_name.to_string(), deps))
             }
         })
-        .collect::<Vec<(String, AHashSet<String>)>>()
+        .collect::<Result<Vec<(String, AHashSet<String>)>, Error>>()?
         .into_iter()
         .for_each(|(module_name, deps)| {
-            if let Some(module) = build_state.modules.get_mut(&module_name) {
-                module.deps = deps.clone();
-            }
+            let previous_deps = match build_state.modules.get_mut(&module_name) {
+                Some(module) => {
+                    module.deps_dirty = false;
+                    std::mem::replace(&mut module.deps, deps.clone())
+                }
+                None => AHashSet::new(),
+            };
+            previous_deps.difference(&deps).for_each(|dep_name| {
+                if let Some(module) = build_state.modules.get_mut(dep_name) {
+                    module.reverse_deps.remove(&module_name);
+                }
+            });
             deps.iter().for_each(|dep_name| {
                 if let Some(module) = build_state.modules.get_mut(dep_name) {
                     module.reverse_deps.insert(module_name.to_string());
                 }
             });
         });
+
+    let dev_dependencies = get_dev_dependencies(build_state);
+    if !dev_dependencies.is_empty() {
+        return Err(Error::DevDependency {
+            dependencies: dev_dependencies,
+        });
+    }
+
+    // modules in a cycle can never be compiled, so we stop before we start compiling
+    let cycles = cycles::find(
+        build_state
+            .modules
+            .iter()
+            .map(|(module_name, module)| (module_name, &module.deps)),
+    );
+    if cycles.is_empty() {
+        Ok(())
+    } else {
+        Err(get_cycle_error(build_state, cycles))
+    }
+}
+
+/// The modules that use a module of a dev source, while they are not a dev module of the same
+/// package. The compiler would find the dev modules, so they are checked before compiling.
+fn get_dev_dependencies(build_state: &BuildState) -> Vec<DependencyStep> {
+    let mut dependencies = build_state
+        .modules
+        .iter()
+        .filter(|(_, module)| matches!(module.source_type, SourceType::SourceFile(_)))
+        .flat_map(|(module_name, module)| {
+            module
+                .deps
+                .iter()
+                .filter(|dep| {
+                    build_state.get_module(dep).is_some_and(|dep| {
+                        dep.is_type_dev
+                            && !(module.is_type_dev && module.package_name == dep.package_name)
+                    })
+                })
+                .map(move |dep| (module_name, dep))
+        })
+        .collect::<Vec<(&String, &String)>>();
+    dependencies.sort();
+    dependencies
+        .into_iter()
+        .map(|(module_name, dep)| DependencyStep {
+            module_name: module_name.to_owned(),
+            dependency: dep.to_owned(),
+            file: get_dependent_file(build_state, module_name, dep),
+        })
+        .collect()
+}
+
+/// The file of `module_name` that depends on `dependency`, the interface when the implementation
+/// doesn't.
+fn get_dependent_file(build_state: &BuildState, module_name: &str, dependency: &str) -> String {
+    let module = build_state
+        .get_module(module_name)
+        .expect("Module not found");
+    let package = build_state
+        .get_package(&module.package_name)
+        .expect("Package not found");
+    match &module.source_type {
+        SourceType::MlMap(_) => {
+            helpers::get_mlmap_path(package, &package.namespace.to_suffix().unwrap_or_default())
+        }
+        SourceType::SourceFile(source_file) => {
+            let ast_path = helpers::get_ast_path(&source_file.implementation.path, package);
+            let in_implementation = get_dep_modules(
+                &ast_path,
+                package.namespace.to_suffix(),
+                package.modules.as_ref().unwrap(),
+                &build_state.module_names,
+            )
+            .map(|deps| deps.contains(dependency))
+            .unwrap_or(true);
+            match &source_file.interface {
+                Some(interface) if !in_implementation => interface.path.to_owned(),
+                _ => source_file.implementation.path.to_owned(),
+            }
+        }
+    }
+}
+
+fn get_cycle_error(build_state: &BuildState, cycles: Vec<Vec<String>>) -> Error {
+    Error::DependencyCycle {
+        cycles: cycles
+            .iter()
+            .map(|cycle| {
+                cycle
+                    .windows(2)
+                    .map(|step| DependencyStep {
+                        module_name: step[0].to_owned(),
+                        dependency: step[1].to_owned(),
+                        file: get_dependent_file(build_state, &step[0], &step[1]),
+                    })
+                    .collect()
+            })
+            .collect(),
+    }
 }
 
-pub fn parse_packages(build_state: &mut BuildState) {
+pub fn parse_packages(build_state: &mut BuildState) -> Result<(), Error> {
     // let mut all_modules: AHashSet<String> = AHashSet::new();
     // let packages = &build_state.packages;
     // let modules = &mut build_state.modules;
 
-    build_state
-        .packages
-        .clone()
-        .iter()
-        .for_each(|(package_name, package)| {
-            debug!("Parsing package: {}", package_name);
-            match package.modules.to_owned() {
-                Some(package_modules) => build_state.module_names.extend(package_modules),
-                None => (),
+    for (package_name, package) in build_state.packages.clone().iter() {
+        debug!("Parsing package: {}", package_name);
+        if let Some(package_modules) = package.modules.to_owned() {
+            build_state.module_names.extend(package_modules)
+        }
+        let build_path_abs = package.get_build_path();
+        helpers::create_build_path(&build_path_abs);
+
+        insert_mlmap(build_state, package);
+
+        debug!("Building source file-tree for package: {}", package.name);
+        match &package.source_files {
+            None => (),
+            Some(source_files) => {
+                for (file, metadata) in source_files.iter() {
+                    insert_source_file(build_state, package, file, metadata)?;
+                }
             }
-            let build_path_abs =
-                helpers::get_build_path(&build_state.project_root, &package.bsconfig.name);
-            helpers::create_build_path(&build_path_abs);
-
-            package.namespace.to_suffix().iter().for_each(|namespace| {
-                // generate the mlmap "AST" file for modules that have a namespace configured
-                let source_files = match package.source_files.to_owned() {
-                    Some(source_files) => source_files
-                        .keys()
-                        .map(|key| key.to_owned())
-                        .collect::<Vec<String>>(),
-                    None => unreachable!(),
-                };
-                let entry = match &package.namespace {
-                    package_tree::Namespace::NamespaceWithEntry {
-                        entry,
-                        namespace: _,
-                    } => Some(entry),
-                    _ => None,
-                };
+        }
+    }
+    Ok(())
+}
 
-                let depending_modules = source_files
-                    .iter()
-                    .map(|path| {
-                        helpers::file_path_to_module_name(
-                            &path,
-                            &package_tree::Namespace::NoNamespace,
-                        )
-                    })
-                    .filter(|module_name| {
-                        if let Some(entry) = entry {
-                            module_name != entry
-                        } else {
-                            true
-                        }
-                    })
-                    .collect::<AHashSet<String>>();
+/// Generates the mlmap "AST" of a package with a namespace, and adds the module of the namespace,
+/// which depends on all the modules of the package.
+pub fn insert_mlmap(build_state: &mut BuildState, package: &package_tree::Package) {
+    package.namespace.to_suffix().iter().for_each(|namespace| {
+        // generate the mlmap "AST" file for modules that have a namespace configured
+        let source_files = match package.source_files.to_owned() {
+            Some(source_files) => source_files
+                .keys()
+                .map(|key| key.to_owned())
+                .collect::<Vec<String>>(),
+            None => unreachable!(),
+        };
+        let entry = match &package.namespace {
+            package_tree::Namespace::NamespaceWithEntry {
+                entry,
+                namespace: _,
+            } => Some(entry),
+            _ => None,
+        };
 
-                let mlmap = gen_mlmap(
-                    &package,
-                    namespace,
-                    depending_modules,
-                    &build_state.project_root,
-                );
+        let depending_modules = source_files
+            .iter()
+            .map(|path| {
+                helpers::file_path_to_module_name(path, &package_tree::Namespace::NoNamespace)
+            })
+            .filter(|module_name| {
+                if let Some(entry) = entry {
+                    module_name != entry
+                } else {
+                    true
+                }
+            })
+            .collect::<AHashSet<String>>();
 
-                // mlmap will be compiled in the AST generation step
-                // compile_mlmap(&package, namespace, &project_root);
+        let mlmap = gen_mlmap(package, namespace, depending_modules);
 
-                let deps = source_files
-                    .iter()
-                    .map(|path| helpers::file_path_to_module_name(&path, &package.namespace))
-                    .filter(|module_name| {
-                        if let Some(entry) = entry {
-                            module_name != entry
-                        } else {
-                            true
-                        }
-                    })
-                    .collect::<AHashSet<String>>();
+        // mlmap will be compiled in the AST generation step
+        // compile_mlmap(&package, namespace, &project_root);
 
-                build_state.insert_module(
-                    &helpers::file_path_to_module_name(
-                        &mlmap.to_owned(),
-                        &package_tree::Namespace::NoNamespace,
-                    ),
-                    Module {
-                        source_type: SourceType::MlMap(MlMap { dirty: false }),
-                        deps: deps,
-                        reverse_deps: AHashSet::new(),
-                        package_name: package.name.to_owned(),
-                        compile_dirty: false,
+        let deps = source_files
+            .iter()
+            .map(|path| helpers::file_path_to_module_name(path, &package.namespace))
+            .filter(|module_name| {
+                if let Some(entry) = entry {
+                    module_name != entry
+                } else {
+                    true
+                }
+            })
+            .collect::<AHashSet<String>>();
+
+        build_state.insert_module(
+            &helpers::file_path_to_module_name(
+                &mlmap.to_owned(),
+                &package_tree::Namespace::NoNamespace,
+            ),
+            Module {
+                source_type: SourceType::MlMap(MlMap { dirty: false }),
+                deps,
+                reverse_deps: AHashSet::new(),
+                package_name: package.name.to_owned(),
+                compile_dirty: false,
+                // the deps are known, but they are registered as reverse deps in get_deps
+                deps_dirty: true,
+                is_type_dev: false,
+            },
+        );
+    });
+}
+
+/// Adds the implementation or the interface of a module, the module is dirty until it's parsed
+/// and compiled.
+pub fn insert_source_file(
+    build_state: &mut BuildState,
+    package: &package_tree::Package,
+    file: &String,
+    metadata: &package_tree::SourceFileMeta,
+) -> Result<(), Error> {
+    let namespace = package.namespace.to_owned();
+
+    let file_buf = PathBuf::from(file);
+    let extension = file_buf.extension().unwrap().to_str().unwrap();
+    let module_name = helpers::file_path_to_module_name(&file.to_owned(), &namespace);
+
+    if helpers::is_implementation_file(extension) {
+        if let Some(Module {
+            source_type: SourceType::SourceFile(source_file),
+            ..
+        }) = build_state.modules.get(&module_name)
+        {
+            if !source_file.implementation.path.is_empty() {
+                return Err(Error::DuplicateModule {
+                    module_name,
+                    first_path: source_file.implementation.path.to_owned(),
+                    second_path: file.to_owned(),
+                });
+            }
+        }
+        build_state
+            .modules
+            .entry(module_name.to_string())
+            .and_modify(|module| {
+                if let SourceType::SourceFile(ref mut source_file) = module.source_type {
+                    source_file.implementation.path = file.to_owned();
+                    source_file.implementation.hash = None;
+                    source_file.implementation.dirty = true;
+                }
+                // the files of the module changed, like when a file is removed from it
+                module.compile_dirty = true;
+                module.deps_dirty = true;
+            })
+            .or_insert(Module {
+                source_type: SourceType::SourceFile(SourceFile {
+                    implementation: Implementation {
+                        path: file.to_owned(),
+                        parse_state: ParseState::Pending,
+                        compile_state: CompileState::Pending,
+                        hash: None,
+                        dirty: true,
+                        parse_diagnostics: vec![],
+                        compile_diagnostics: vec![],
                     },
-                );
+                    interface: None,
+                }),
+                deps: AHashSet::new(),
+                reverse_deps: AHashSet::new(),
+                package_name: package.name.to_owned(),
+                compile_dirty: true,
+                deps_dirty: true,
+                is_type_dev: metadata.is_type_dev,
             });
-
-            debug!("Building source file-tree for package: {}", package.name);
-            match &package.source_files {
-                None => (),
-                Some(source_files) => source_files.iter().for_each(|(file, metadata)| {
-                    let namespace = package.namespace.to_owned();
-
-                    let file_buf = PathBuf::from(file);
-                    let extension = file_buf.extension().unwrap().to_str().unwrap();
-                    let module_name =
-                        helpers::file_path_to_module_name(&file.to_owned(), &namespace);
-
-                    if helpers::is_implementation_file(extension) {
-                        build_state
-                            .modules
-                            .entry(module_name.to_string())
-                            .and_modify(|module| match module.source_type {
-                                SourceType::SourceFile(ref mut source_file) => {
-                                    if source_file.implementation.path.len() > 0 {
-                                        error!(
-                                            "Duplicate files found for module: {}",
-                                            &module_name
-                                        );
-                                        error!("file 1: {}", &source_file.implementation.path);
-                                        error!("file 2: {}", &file);
-
-                                        panic!("Unable to continue... See log output above...");
-                                    }
-                                    source_file.implementation.path = file.to_owned();
-                                    source_file.implementation.last_modified = metadata.modified;
-                                    source_file.implementation.dirty = true;
-                                }
-                                _ => (),
-                            })
-                            .or_insert(Module {
-                                source_type: SourceType::SourceFile(SourceFile {
-                                    implementation: Implementation {
-                                        path: file.to_owned(),
-                                        parse_state: ParseState::Pending,
-                                        compile_state: CompileState::Pending,
-                                        last_modified: metadata.modified,
-                                        dirty: true,
-                                    },
-                                    interface: None,
-                                }),
-                                deps: AHashSet::new(),
-                                reverse_deps: AHashSet::new(),
-                                package_name: package.name.to_owned(),
-                                compile_dirty: true,
-                            });
-                    } else {
-                        build_state
-                            .modules
-                            .entry(module_name.to_string())
-                            .and_modify(|module| match module.source_type {
-                                SourceType::SourceFile(ref mut source_file) => {
-                                    source_file.interface = Some(Interface {
-                                        path: file.to_owned(),
-                                        parse_state: ParseState::Pending,
-                                        compile_state: CompileState::Pending,
-                                        last_modified: metadata.modified,
-                                        dirty: true,
-                                    });
-                                }
-                                _ => (),
-                            })
-                            .or_insert(Module {
-                                source_type: SourceType::SourceFile(SourceFile {
-                                    // this will be overwritten later
-                                    implementation: Implementation {
-                                        path: "".to_string(),
-                                        parse_state: ParseState::Pending,
-                                        compile_state: CompileState::Pending,
-                                        last_modified: metadata.modified,
-                                        dirty: false,
-                                    },
-                                    interface: Some(Interface {
-                                        path: file.to_owned(),
-                                        parse_state: ParseState::Pending,
-                                        compile_state: CompileState::Pending,
-                                        last_modified: metadata.modified,
-                                        dirty: true,
-                                    }),
-                                }),
-                                deps: AHashSet::new(),
-                                reverse_deps: AHashSet::new(),
-                                package_name: package.name.to_owned(),
-                                compile_dirty: true,
-                            });
-                    }
+    } else {
+        build_state
+            .modules
+            .entry(module_name.to_string())
+            .and_modify(|module| {
+                if let SourceType::SourceFile(ref mut source_file) = module.source_type {
+                    source_file.interface = Some(Interface {
+                        path: file.to_owned(),
+                        parse_state: ParseState::Pending,
+                        compile_state: CompileState::Pending,
+                        hash: None,
+                        dirty: true,
+                        parse_diagnostics: vec![],
+                        compile_diagnostics: vec![],
+                    });
+                }
+                module.compile_dirty = true;
+                module.deps_dirty = true;
+            })
+            .or_insert(Module {
+                source_type: SourceType::SourceFile(SourceFile {
+                    // this will be overwritten later
+                    implementation: Implementation {
+                        path: "".to_string(),
+                        parse_state: ParseState::Pending,
+                        compile_state: CompileState::Pending,
+                        hash: None,
+                        dirty: false,
+                        parse_diagnostics: vec![],
+                        compile_diagnostics: vec![],
+                    },
+                    interface: Some(Interface {
+                        path: file.to_owned(),
+                        parse_state: ParseState::Pending,
+                        compile_state: CompileState::Pending,
+                        hash: None,
+                        dirty: true,
+                        parse_diagnostics: vec![],
+                        compile_diagnostics: vec![],
+                    }),
                 }),
-            }
-        });
+                deps: AHashSet::new(),
+                reverse_deps: AHashSet::new(),
+                package_name: package.name.to_owned(),
+                compile_dirty: true,
+                deps_dirty: true,
+                is_type_dev: metadata.is_type_dev,
+            });
+    }
+    Ok(())
 }
 
-pub fn compile_mlmap(package: &package_tree::Package, namespace: &str, root_path: &str) {
-    let build_path_abs = helpers::get_build_path(root_path, &package.name);
+pub fn compile_mlmap(
+    package: &package_tree::Package,
+    namespace: &str,
+    root_path: &str,
+) -> Result<(), String> {
+    let build_path_abs = package.get_build_path();
     let mlmap_name = format!("{}.mlmap", namespace);
     let args = vec![
         "-w",
@@ -778,24 +973,85 @@ pub fn compile_mlmap(package: &package_tree::Package, namespace: &str, root_path
         &mlmap_name,
     ];
 
-    let _ = Command::new(helpers::get_bsc(&root_path))
+    Command::new(helpers::get_bsc(root_path).map_err(|e| e.to_string())?)
         .current_dir(helpers::canonicalize_string_path(&build_path_abs).unwrap())
         .args(args)
         .output()
-        .expect("err");
+        .map(|_| ())
+        .map_err(|e| format!("Could not run bsc to compile {}: {}\n", mlmap_name, e))
 }
 
-pub fn compile_file(
+/// The files bsc writes when it compiles the implementation or the interface of a module.
+fn get_compile_outputs(
+    package: &package_tree::Package,
+    root_package: &package_tree::Package,
+    module: &Module,
+    is_interface: bool,
+) -> Vec<String> {
+    let implementation_file_path = match module.source_type {
+        SourceType::SourceFile(ref source_file) => &source_file.implementation.path,
+        _ => panic!("Not a source file"),
+    };
+    let compiler_asset = |extension| {
+        helpers::get_compiler_asset(
+            implementation_file_path,
+            package,
+            &package.namespace,
+            extension,
+        )
+    };
+    if is_interface {
+        return vec![compiler_asset("cmi"), compiler_asset("cmti")];
+    }
+
+    let js_files = helpers::get_js_paths(implementation_file_path, package, root_package);
+    let compiler_assets = match get_interface(module) {
+        // the cmi is generated when compiling the interface
+        Some(_) => vec![compiler_asset("cmj"), compiler_asset("cmt")],
+        None => vec![
+            compiler_asset("cmi"),
+            compiler_asset("cmj"),
+            compiler_asset("cmt"),
+        ],
+    };
+    [compiler_assets, js_files].concat()
+}
+
+/// The `-bs-package-output` arguments for an implementation. The package specs of the root package
+/// are used for all packages, bsc writes a JavaScript file for every one of them.
+fn get_package_output_args(
+    implementation_file_path: &str,
+    package: &package_tree::Package,
+    root_package: &package_tree::Package,
+) -> Vec<String> {
+    bsconfig::get_package_specs(&root_package.bsconfig)
+        .iter()
+        .flat_map(|package_spec| {
+            vec![
+                "-bs-package-output".to_string(),
+                format!(
+                    "{}:{}:{}",
+                    package_spec.module,
+                    helpers::get_package_output_dir(
+                        implementation_file_path,
+                        package,
+                        package_spec
+                    ),
+                    bsconfig::get_suffix(package_spec, &package.bsconfig, &root_package.bsconfig)
+                ),
+            ]
+        })
+        .collect::<Vec<String>>()
+}
+
+/// All the arguments bsc is called with to compile a module, apart from the AST itself.
+pub fn get_compiler_args(
     package: &package_tree::Package,
     root_package: &package_tree::Package,
-    ast_path: &str,
     module: &Module,
-    root_path: &str,
     version: &str,
     is_interface: bool,
-) -> Result<Option<String>, String> {
-    let build_path_abs = helpers::get_build_path(root_path, &package.name);
-    let pkg_path_abs = helpers::get_package_path(root_path, &package.name);
+) -> Vec<String> {
     let bsc_flags = bsconfig::flatten_flags(&package.bsconfig.bsc_flags);
 
     let normal_deps = package
@@ -805,23 +1061,27 @@ pub fn compile_file(
         .unwrap_or(&vec![])
         .to_owned();
 
-    // don't compile dev-deps yet
-    // let dev_deps = source
-    //     .package
-    //     .bsconfig
-    //     .bs_dev_dependencies
-    //     .as_ref()
-    //     .unwrap_or(&vec![])
-    //     .to_owned();
+    // only dev modules can use the dev dependencies
+    let dev_deps = if module.is_type_dev {
+        package
+            .bsconfig
+            .bs_dev_dependencies
+            .as_ref()
+            .unwrap_or(&vec![])
+            .to_owned()
+    } else {
+        vec![]
+    };
 
-    let deps = vec![normal_deps]
+    let deps = [normal_deps, dev_deps]
         .concat()
         .into_iter()
         .map(|x| {
-            vec![
-                "-I".to_string(),
-                helpers::canonicalize_string_path(&helpers::get_build_path(root_path, &x)).unwrap(),
-            ]
+            // the dependencies are found the same way as when the package tree is made, so the
+            // path is canonical
+            let package_dir = helpers::resolve_package(&x, &package.package_dir)
+                .unwrap_or(format!("{}/node_modules/{}", package.package_dir, x));
+            vec!["-I".to_string(), package_dir + "/lib/ocaml"]
         })
         .collect::<Vec<Vec<String>>>();
 
@@ -857,34 +1117,12 @@ pub fn compile_file(
         package_tree::Namespace::NoNamespace => vec![],
     };
 
-    let jsx_args = get_jsx_args(&root_package);
-    let jsx_module_args = get_jsx_module_args(&root_package);
-    let jsx_mode_args = get_jsx_mode_args(&root_package);
-    let uncurried_args = get_uncurried_args(version, &package, &root_package);
+    let jsx_args = get_jsx_args(root_package);
+    let jsx_module_args = get_jsx_module_args(root_package);
+    let jsx_mode_args = get_jsx_mode_args(root_package);
+    let uncurried_args = get_uncurried_args(version, package, root_package);
 
-    let warning_args: Vec<String> = match root_package.bsconfig.warnings.to_owned() {
-        None => vec![],
-        Some(warnings) => {
-            let warn_number = match warnings.number {
-                None => vec![],
-                Some(warnings) => {
-                    vec!["-w".to_string(), warnings.to_string()]
-                }
-            };
-
-            let warn_error = match warnings.error {
-                Some(bsconfig::Error::Catchall(true)) => {
-                    vec!["-warn-error".to_string(), "A".to_string()]
-                }
-                Some(bsconfig::Error::Qualified(errors)) => {
-                    vec!["-warn-error".to_string(), errors.to_string()]
-                }
-                _ => vec![],
-            };
-
-            vec![warn_number, warn_error].concat()
-        }
-    };
+    let warning_args = get_warning_args(root_package);
 
     let read_cmi_args = match get_interface(module) {
         Some(_) => {
@@ -898,36 +1136,22 @@ pub fn compile_file(
     };
 
     let implementation_args = if is_interface {
-        debug!("Compiling interface file: {}", &module_name);
         vec![]
     } else {
-        debug!("Compiling file: {}", &module_name);
-
-        // TODO: Also read suffix from package-spec.
-        let suffix = match root_package.bsconfig.suffix.to_owned() {
-            Some(suffix) => suffix,
-            None => bsconfig::Suffix::Mjs,
-        };
+        let package_output_args =
+            get_package_output_args(implementation_file_path, package, root_package);
 
-        vec![
-            "-bs-package-name".to_string(),
-            package.bsconfig.name.to_owned(),
-            "-bs-package-output".to_string(),
-            format!(
-                "es6:{}:{}",
-                Path::new(implementation_file_path)
-                    .strip_prefix(pkg_path_abs)
-                    .unwrap()
-                    .parent()
-                    .unwrap()
-                    .to_str()
-                    .unwrap(),
-                suffix
-            ),
+        [
+            vec![
+                "-bs-package-name".to_string(),
+                package.bsconfig.name.to_owned(),
+            ],
+            package_output_args,
         ]
+        .concat()
     };
 
-    let to_mjs_args = vec![
+    [
         namespace_args,
         read_cmi_args,
         vec!["-I".to_string(), ".".to_string()],
@@ -948,96 +1172,179 @@ pub fn compile_file(
         //     "-I".to_string(),
         //     abs_node_modules_path.to_string() + "/rescript/ocaml",
         // ],
-        vec![helpers::canonicalize_string_path(&ast_path.to_owned()).unwrap()],
     ]
-    .concat();
+    .concat()
+}
+
+/// Compiles the AST of an implementation or an interface. Returns the warnings to report, and
+/// whether the outputs were restored from the cache instead of compiled.
+#[allow(clippy::too_many_arguments)]
+pub fn compile_file(
+    package: &package_tree::Package,
+    root_package: &package_tree::Package,
+    ast_path: &str,
+    module: &Module,
+    root_path: &str,
+    version: &str,
+    is_interface: bool,
+    cache: Option<&cache::CacheInputs>,
+    cancel_token: Option<&cancel::CancelToken>,
+) -> Result<(Option<String>, bool), String> {
+    let build_path_abs = package.get_build_path();
+    let implementation_file_path = match module.source_type {
+        SourceType::SourceFile(ref source_file) => &source_file.implementation.path,
+        _ => panic!("Not a source file"),
+    };
+    let module_name =
+        helpers::file_path_to_module_name(implementation_file_path, &package.namespace);
+
+    if is_interface {
+        debug!("Compiling interface file: {}", &module_name);
+    } else {
+        debug!("Compiling file: {}", &module_name);
+    }
 
-    let to_mjs = Command::new(helpers::get_bsc(&root_path))
-        .current_dir(helpers::canonicalize_string_path(&build_path_abs.to_owned()).unwrap())
-        .args(to_mjs_args)
-        .output();
+    let to_mjs_args = [
+        get_compiler_args(package, root_package, module, version, is_interface),
+        vec![helpers::canonicalize_string_path(ast_path).unwrap()],
+    ]
+    .concat();
 
-    match to_mjs {
-        Ok(x) if !x.status.success() => {
-            let stderr = String::from_utf8_lossy(&x.stderr);
-            let stdout = String::from_utf8_lossy(&x.stdout);
-            Err(stderr.to_string() + &stdout)
+    let outputs = get_compile_outputs(package, root_package, module, is_interface);
+    let cache_key = cache.and_then(|inputs| {
+        let mut dependency_digests = inputs.dependency_digests.to_owned();
+        if !is_interface && get_interface(module).is_some() {
+            // the implementation is compiled against the cmi of its own interface
+            dependency_digests.push(
+                helpers::compute_file_hash(&helpers::get_compiler_asset(
+                    implementation_file_path,
+                    package,
+                    &package.namespace,
+                    "cmi",
+                ))
+                .as_ref()
+                .map(state::hash_to_string)?,
+            );
         }
-        Err(e) => Err(format!("ERROR, {}, {:?}", e, ast_path)),
-        Ok(x) => {
-            let err = std::str::from_utf8(&x.stderr)
-                .expect("stdout should be non-null")
-                .to_string();
-
-            let dir = std::path::Path::new(implementation_file_path)
-                .strip_prefix(helpers::get_package_path(root_path, &package.name))
-                .unwrap()
-                .parent()
-                .unwrap();
+        cache::get_key(version, &to_mjs_args, ast_path, &dependency_digests)
+    });
+    let restored = match (cache, &cache_key) {
+        (Some(inputs), Some(key)) => inputs.cache.restore(key, &outputs),
+        _ => None,
+    };
 
-            // perhaps we can do this copying somewhere else
-            if !is_interface {
-                let _ = std::fs::copy(
-                    build_path_abs.to_string() + "/" + &module_name + ".cmi",
-                    std::path::Path::new(&helpers::get_bs_build_path(root_path, &package.name))
-                        .join(dir)
-                        .join(module_name.to_owned() + ".cmi"),
-                );
-                let _ = std::fs::copy(
-                    build_path_abs.to_string() + "/" + &module_name + ".cmj",
-                    std::path::Path::new(&helpers::get_bs_build_path(root_path, &package.name))
-                        .join(dir)
-                        .join(module_name.to_owned() + ".cmj"),
-                );
-                let _ = std::fs::copy(
-                    build_path_abs.to_string() + "/" + &module_name + ".cmt",
-                    std::path::Path::new(&helpers::get_bs_build_path(root_path, &package.name))
-                        .join(dir)
-                        .join(module_name.to_owned() + ".cmt"),
-                );
-            } else {
-                let _ = std::fs::copy(
-                    build_path_abs.to_string() + "/" + &module_name + ".cmti",
-                    std::path::Path::new(&helpers::get_bs_build_path(root_path, &package.name))
-                        .join(dir)
-                        .join(module_name.to_owned() + ".cmti"),
-                );
-            }
+    let is_restored = restored.is_some();
+    let err = match restored {
+        Some(err) => {
+            debug!("Restored {} from the cache", &module_name);
+            err
+        }
+        None => {
+            let to_mjs = cancel::output(
+                Command::new(helpers::get_bsc(root_path).map_err(|e| e.to_string())?)
+                    .current_dir(
+                        helpers::canonicalize_string_path(&build_path_abs.to_owned()).unwrap(),
+                    )
+                    .args(&to_mjs_args),
+                cancel_token,
+            );
 
-            if helpers::contains_ascii_characters(&err) {
-                if package.is_pinned_dep {
-                    // supress warnings of external deps
-                    Ok(Some(err))
-                } else {
-                    Ok(None)
+            match to_mjs {
+                Ok(x) if !x.status.success() => {
+                    let stderr = String::from_utf8_lossy(&x.stderr);
+                    let stdout = String::from_utf8_lossy(&x.stdout);
+                    return Err(stderr.to_string() + &stdout);
+                }
+                Err(e) => return Err(format!("ERROR, {}, {:?}", e, ast_path)),
+                Ok(x) => {
+                    let err = std::str::from_utf8(&x.stderr)
+                        .expect("stdout should be non-null")
+                        .to_string();
+                    if let (Some(inputs), Some(key)) = (cache, &cache_key) {
+                        inputs.cache.store(key, &outputs, &err);
+                    }
+                    err
                 }
-            } else {
-                Ok(None)
             }
         }
+    };
+
+    let dir = std::path::Path::new(implementation_file_path)
+        .strip_prefix(&package.package_dir)
+        .unwrap()
+        .parent()
+        .unwrap();
+
+    // perhaps we can do this copying somewhere else
+    if !is_interface {
+        let _ = std::fs::copy(
+            build_path_abs.to_string() + "/" + &module_name + ".cmi",
+            std::path::Path::new(&package.get_bs_build_path())
+                .join(dir)
+                .join(module_name.to_owned() + ".cmi"),
+        );
+        let _ = std::fs::copy(
+            build_path_abs.to_string() + "/" + &module_name + ".cmj",
+            std::path::Path::new(&package.get_bs_build_path())
+                .join(dir)
+                .join(module_name.to_owned() + ".cmj"),
+        );
+        let _ = std::fs::copy(
+            build_path_abs.to_string() + "/" + &module_name + ".cmt",
+            std::path::Path::new(&package.get_bs_build_path())
+                .join(dir)
+                .join(module_name.to_owned() + ".cmt"),
+        );
+    } else {
+        let _ = std::fs::copy(
+            build_path_abs.to_string() + "/" + &module_name + ".cmti",
+            std::path::Path::new(&package.get_bs_build_path())
+                .join(dir)
+                .join(module_name.to_owned() + ".cmti"),
+        );
+    }
+
+    if helpers::contains_ascii_characters(&err) {
+        if package.is_pinned_dep {
+            // supress warnings of external deps
+            Ok((Some(err), is_restored))
+        } else {
+            Ok((None, is_restored))
+        }
+    } else {
+        Ok((None, is_restored))
     }
 }
 
-pub fn clean(path: &str) {
+pub fn clean(path: &str, show_progress: bool, no_timing: bool) -> Result<(), Error> {
+    let default_timing: Option<Duration> = if no_timing {
+        Some(Duration::new(0, 0))
+    } else {
+        None
+    };
     let project_root = helpers::get_abs_path(path);
-    let packages = package_tree::make(&None, &project_root);
-    let root_config_name = package_tree::get_package_name(&project_root);
+    let packages = package_tree::make(&None, &project_root)?;
+    let root_config_name = package_tree::get_package_name(&project_root)?;
 
     let timing_clean_compiler_assets = Instant::now();
-    print!(
-        "{} {} Cleaning compiler assets...",
-        style("[1/2]").bold().dim(),
-        SWEEP
-    );
-    std::io::stdout().flush().unwrap();
-    packages.iter().for_each(|(_, package)| {
+    if show_progress {
         print!(
-            "{}\r{} {} Cleaning {}...",
-            LINE_CLEAR,
+            "{} {} Cleaning compiler assets...",
             style("[1/2]").bold().dim(),
-            SWEEP,
-            package.name
+            SWEEP
         );
+    }
+    std::io::stdout().flush().unwrap();
+    packages.iter().for_each(|(_, package)| {
+        if show_progress {
+            print!(
+                "{}\r{} {} Cleaning {}...",
+                LINE_CLEAR,
+                style("[1/2]").bold().dim(),
+                SWEEP,
+                package.name
+            );
+        }
         std::io::stdout().flush().unwrap();
 
         let path = std::path::Path::new(&package.package_dir)
@@ -1051,34 +1358,45 @@ pub fn clean(path: &str) {
     });
     let timing_clean_compiler_assets_elapsed = timing_clean_compiler_assets.elapsed();
 
-    println!(
-        "{}\r{} {}Cleant compiler assets in {:.2}s",
-        LINE_CLEAR,
-        style("[1/2]").bold().dim(),
-        CHECKMARK,
-        timing_clean_compiler_assets_elapsed.as_secs_f64()
-    );
+    if show_progress {
+        println!(
+            "{}\r{} {}Cleant compiler assets in {:.2}s",
+            LINE_CLEAR,
+            style("[1/2]").bold().dim(),
+            CHECKMARK,
+            default_timing
+                .unwrap_or(timing_clean_compiler_assets_elapsed)
+                .as_secs_f64()
+        );
+    }
     std::io::stdout().flush().unwrap();
 
     let timing_clean_mjs = Instant::now();
-    print!(
-        "{} {} Clearing mjs files...",
-        style("[2/2]").bold().dim(),
-        SWEEP
-    );
+    if show_progress {
+        print!(
+            "{} {} Clearing mjs files...",
+            style("[2/2]").bold().dim(),
+            SWEEP
+        );
+    }
     std::io::stdout().flush().unwrap();
     let mut build_state = BuildState::new(project_root, root_config_name, packages);
-    parse_packages(&mut build_state);
-    clean_mjs_files(&build_state.modules);
+    parse_packages(&mut build_state)?;
+    clean_js_files(&build_state);
     let timing_clean_mjs_elapsed = timing_clean_mjs.elapsed();
-    println!(
-        "{}\r{} {}Cleant mjs in {:.2}s",
-        LINE_CLEAR,
-        style("[2/2]").bold().dim(),
-        CHECKMARK,
-        timing_clean_mjs_elapsed.as_secs_f64()
-    );
+    if show_progress {
+        println!(
+            "{}\r{} {}Cleant mjs in {:.2}s",
+            LINE_CLEAR,
+            style("[2/2]").bold().dim(),
+            CHECKMARK,
+            default_timing
+                .unwrap_or(timing_clean_mjs_elapsed)
+                .as_secs_f64()
+        );
+    }
     std::io::stdout().flush().unwrap();
+    Ok(())
 }
 
 fn is_dirty(module: &Module) -> bool {
@@ -1096,78 +1414,377 @@ fn is_dirty(module: &Module) -> bool {
     }
 }
 
-fn compute_file_hash(path: &str) -> Option<blake3::Hash> {
-    match fs::read(path) {
-        Ok(str) => Some(blake3::hash(&str)),
-        Err(_) => None,
+/// The digests of the cmi files of the dependencies of a module. Returns `None` when one of them
+/// has not been compiled (successfully).
+fn get_dependency_digests(build_state: &BuildState, module: &Module) -> Option<Vec<String>> {
+    let mut deps = module.deps.iter().collect::<Vec<&String>>();
+    deps.sort();
+    deps.into_iter()
+        .map(|dep_name| {
+            let dep = build_state.get_module(dep_name)?;
+            let package = build_state.get_package(&dep.package_name)?;
+            let cmi_path = match &dep.source_type {
+                SourceType::SourceFile(source_file) => helpers::get_compiler_asset(
+                    &source_file.implementation.path,
+                    package,
+                    &package.namespace,
+                    "cmi",
+                ),
+                SourceType::MlMap(_) => helpers::get_mlmap_compile_path(package, dep_name),
+            };
+            helpers::compute_file_hash(&cmi_path)
+                .as_ref()
+                .map(state::hash_to_string)
+        })
+        .collect()
+}
+
+/// Compiles the interface (if any) and the implementation of a single module. This is called from
+/// the workers of the compile scheduler, so it only needs read access to the build state.
+fn compile_module(
+    build_state: &BuildState,
+    module_name: &str,
+    version: &str,
+    cache: Option<&cache::Cache>,
+    cancel_token: Option<&cancel::CancelToken>,
+) -> scheduler::CompileOutcome {
+    let module = build_state.get_module(module_name).unwrap();
+    let package = build_state
+        .get_package(&module.package_name)
+        .expect("Package not found");
+
+    match &module.source_type {
+        SourceType::MlMap(_) => {
+            // the mlmap needs to be compiled before the files are compiled
+            // in the same namespace, otherwise we get a compile error
+            // this is why mlmap is compiled in the AST generation stage
+            // compile_mlmap(&module.package, module_name, &project_root);
+            scheduler::CompileOutcome {
+                module_name: module_name.to_string(),
+                result: Ok(None),
+                interface_result: Some(Ok(None)),
+                is_clean: false,
+                is_compiled: false,
+                is_restored: false,
+                duration: Duration::ZERO,
+            }
+        }
+        SourceType::SourceFile(source_file) => {
+            let cmi_path = helpers::get_compiler_asset(
+                &source_file.implementation.path,
+                package,
+                &package.namespace,
+                "cmi",
+            );
+
+            let cmi_digest = helpers::compute_file_hash(&cmi_path);
+
+            let root_package = build_state
+                .get_package(&build_state.root_config_name)
+                .unwrap();
+            let cache_inputs = cache.and_then(|cache| {
+                Some(cache::CacheInputs {
+                    cache,
+                    dependency_digests: get_dependency_digests(build_state, module)?,
+                })
+            });
+
+            let interface_result = source_file.interface.as_ref().map(|interface| {
+                compile_file(
+                    package,
+                    root_package,
+                    &helpers::get_iast_path(&interface.path, package),
+                    module,
+                    &build_state.project_root,
+                    version,
+                    true,
+                    cache_inputs.as_ref(),
+                    cancel_token,
+                )
+            });
+            let result = compile_file(
+                package,
+                root_package,
+                &helpers::get_ast_path(&source_file.implementation.path, package),
+                module,
+                &build_state.project_root,
+                version,
+                false,
+                cache_inputs.as_ref(),
+                cancel_token,
+            );
+            let cmi_digest_after = helpers::compute_file_hash(&cmi_path);
+            // bsc didn't run when all the files of the module came from the cache
+            let is_restored = matches!(result, Ok((_, true)))
+                && interface_result
+                    .as_ref()
+                    .map(|result| matches!(result, Ok((_, true))))
+                    .unwrap_or(true);
+
+            // we want to compare both the hash of interface and the implementation
+            // compile assets to verify that nothing changed. We also need to checke the interface
+            // because we can include MyModule, so the modules that depend on this module might
+            // change when this modules interface does not change, but the implementation does
+            let is_clean_cmi = match (cmi_digest, cmi_digest_after) {
+                (Some(cmi_digest), Some(cmi_digest_after)) => cmi_digest.eq(&cmi_digest_after),
+
+                _ => false,
+            };
+
+            scheduler::CompileOutcome {
+                module_name: module_name.to_string(),
+                result: result.map(|(warnings, _)| warnings),
+                interface_result: interface_result
+                    .map(|result| result.map(|(warnings, _)| warnings)),
+                is_clean: is_clean_cmi,
+                is_compiled: !is_restored,
+                is_restored,
+                duration: Duration::ZERO,
+            }
+        }
     }
 }
 
-pub fn build(filter: &Option<regex::Regex>, path: &str) -> Result<BuildState, ()> {
+/// Builds the project in `path`. When there is a `report_target`, a JSON report of the build is
+/// written to it, and when there is a `sarif_path` the warnings and errors are written to it as a
+/// SARIF log, also when the build fails. When there is a `cache`, the outputs of the compiled
+/// modules are stored in it, and restored from it instead of compiled when possible.
+pub fn build(
+    filter: &Option<regex::Regex>,
+    path: &str,
+    show_progress: bool,
+    no_timing: bool,
+    report_target: Option<&report::Target>,
+    sarif_path: Option<&Path>,
+    cache: Option<&cache::Cache>,
+) -> Result<BuildState, Error> {
+    let mut build_report = report::BuildReport::default();
+    // stdout is reserved for the report, so the progress is hidden and the warnings and errors of
+    // the compiler are printed to stderr
+    let report_to_stdout = matches!(report_target, Some(report::Target::Stdout));
+    let result = build_with_report(
+        filter,
+        path,
+        show_progress && !report_to_stdout,
+        no_timing,
+        report_to_stdout,
+        &mut build_report,
+        cache,
+    );
+    let written_sarif = match sarif_path {
+        Some(sarif_path) => sarif::write(
+            sarif_path,
+            build_report.diagnostics(),
+            &helpers::get_abs_path(path),
+        ),
+        None => Ok(()),
+    };
+    let written_report = match report_target {
+        Some(target) => {
+            build_report.finish(&result);
+            build_report.write(target)
+        }
+        None => Ok(()),
+    };
+    result.and_then(|build_state| written_sarif.and(written_report).map(|_| build_state))
+}
+
+fn build_with_report(
+    filter: &Option<regex::Regex>,
+    path: &str,
+    show_progress: bool,
+    no_timing: bool,
+    diagnostics_to_stderr: bool,
+    build_report: &mut report::BuildReport,
+    cache: Option<&cache::Cache>,
+) -> Result<BuildState, Error> {
+    let default_timing: Option<Duration> = if no_timing {
+        Some(Duration::new(0, 0))
+    } else {
+        None
+    };
     let timing_total = Instant::now();
+    let (mut build_state, rescript_version, deleted_module_names) =
+        initialize_build(filter, path, show_progress, no_timing, build_report)?;
+    incremental_build(
+        &mut build_state,
+        &rescript_version,
+        &deleted_module_names,
+        show_progress,
+        no_timing,
+        diagnostics_to_stderr,
+        build_report,
+        cache,
+        None,
+    )?;
+
+    let timing_total_elapsed = timing_total.elapsed();
+    build_report.timings.total = Some(default_timing.unwrap_or(timing_total_elapsed).as_secs_f64());
+    if show_progress {
+        println!(
+            "Done in {:.2}s",
+            default_timing.unwrap_or(timing_total_elapsed).as_secs_f64()
+        );
+    }
+
+    Ok(build_state)
+}
+
+/// Reads the package tree, finds the source files and figures out what changed since the previous
+/// build. Returns the build state with the modules that need to be parsed or compiled marked as
+/// dirty, the version of the compiler, and the modules that were deleted since the previous build.
+pub fn initialize_build(
+    filter: &Option<regex::Regex>,
+    path: &str,
+    show_progress: bool,
+    no_timing: bool,
+    build_report: &mut report::BuildReport,
+) -> Result<(BuildState, String, AHashSet<String>), Error> {
+    let default_timing: Option<Duration> = if no_timing {
+        Some(Duration::new(0, 0))
+    } else {
+        None
+    };
     let project_root = helpers::get_abs_path(path);
-    let rescript_version = get_version(&project_root);
-    let root_config_name = package_tree::get_package_name(&project_root);
+    let root_config_name = package_tree::get_package_name(&project_root)?;
+    let rescript_version = get_version(&project_root)?;
+    build_report.rescript_version = Some(rescript_version.to_owned());
 
-    print!(
-        "{} {} Building package tree...",
-        style("[1/6]").bold().dim(),
-        TREE
-    );
+    if show_progress {
+        print!(
+            "{} {} Building package tree...",
+            style("[1/6]").bold().dim(),
+            TREE
+        );
+    }
     let _ = stdout().flush();
     let timing_package_tree = Instant::now();
-    let packages = package_tree::make(&filter, &project_root);
+    let packages = package_tree::make(filter, &project_root)?;
     let timing_package_tree_elapsed = timing_package_tree.elapsed();
-    logs::initialize(&packages);
-
-    println!(
-        "{}\r{} {}Built package tree in {:.2}s",
-        LINE_CLEAR,
-        style("[1/6]").bold().dim(),
-        CHECKMARK,
-        timing_package_tree_elapsed.as_secs_f64()
+    build_report.timings.package_tree = Some(
+        default_timing
+            .unwrap_or(timing_package_tree_elapsed)
+            .as_secs_f64(),
     );
+    if show_progress {
+        println!(
+            "{}\r{} {}Built package tree in {:.2}s",
+            LINE_CLEAR,
+            style("[1/6]").bold().dim(),
+            CHECKMARK,
+            default_timing
+                .unwrap_or(timing_package_tree_elapsed)
+                .as_secs_f64()
+        );
+    }
 
     let timing_source_files = Instant::now();
-    print!(
-        "{} {} Finding source files...",
-        style("[2/6]").bold().dim(),
-        LOOKING_GLASS
-    );
+    if show_progress {
+        print!(
+            "{} {} Finding source files...",
+            style("[2/6]").bold().dim(),
+            LOOKING_GLASS
+        );
+    }
     let _ = stdout().flush();
     let mut build_state = BuildState::new(project_root, root_config_name, packages);
-    parse_packages(&mut build_state);
+    parse_packages(&mut build_state)?;
     let timing_source_files_elapsed = timing_source_files.elapsed();
-    println!(
-        "{}\r{} {}Found source files in {:.2}s",
-        LINE_CLEAR,
-        style("[2/6]").bold().dim(),
-        CHECKMARK,
-        timing_source_files_elapsed.as_secs_f64()
+    build_report.timings.source_files = Some(
+        default_timing
+            .unwrap_or(timing_source_files_elapsed)
+            .as_secs_f64(),
     );
+    if show_progress {
+        println!(
+            "{}\r{} {}Found source files in {:.2}s",
+            LINE_CLEAR,
+            style("[2/6]").bold().dim(),
+            CHECKMARK,
+            default_timing
+                .unwrap_or(timing_source_files_elapsed)
+                .as_secs_f64()
+        );
+    }
 
-    print!(
-        "{} {} Cleaning up previous build...",
-        style("[3/6]").bold().dim(),
-        SWEEP
-    );
+    if show_progress {
+        print!(
+            "{} {} Cleaning up previous build...",
+            style("[3/6]").bold().dim(),
+            SWEEP
+        );
+    }
     let timing_cleanup = Instant::now();
     let (diff_cleanup, total_cleanup, deleted_module_names) =
-        clean::cleanup_previous_build(&mut build_state);
+        clean::cleanup_previous_build(&mut build_state, &rescript_version);
     let timing_cleanup_elapsed = timing_cleanup.elapsed();
-    println!(
-        "{}\r{} {}Cleaned {}/{} {:.2}s",
-        LINE_CLEAR,
-        style("[3/6]").bold().dim(),
-        CHECKMARK,
-        diff_cleanup,
-        total_cleanup,
-        timing_cleanup_elapsed.as_secs_f64()
+    build_report.timings.cleanup = Some(
+        default_timing
+            .unwrap_or(timing_cleanup_elapsed)
+            .as_secs_f64(),
     );
+    if show_progress {
+        println!(
+            "{}\r{} {}Cleaned {}/{} {:.2}s",
+            LINE_CLEAR,
+            style("[3/6]").bold().dim(),
+            CHECKMARK,
+            diff_cleanup,
+            total_cleanup,
+            default_timing
+                .unwrap_or(timing_cleanup_elapsed)
+                .as_secs_f64()
+        );
+    }
+
+    Ok((build_state, rescript_version, deleted_module_names))
+}
 
-    let num_dirty_modules = build_state.modules.values().filter(|m| is_dirty(m)).count() as u64;
+/// Parses the dirty modules, updates the dependencies of the modules that were parsed, and
+/// compiles the modules that are dirty and the modules that depend on them. The watcher calls this
+/// with the build state of the previous build, after it marked the changed modules as dirty. When
+/// the `cancel_token` gets cancelled while compiling, the modules that didn't get compiled stay
+/// dirty, and the build stops with `Error::Cancelled`.
+#[allow(clippy::too_many_arguments)]
+pub fn incremental_build(
+    build_state: &mut BuildState,
+    rescript_version: &str,
+    deleted_module_names: &AHashSet<String>,
+    show_progress: bool,
+    no_timing: bool,
+    diagnostics_to_stderr: bool,
+    build_report: &mut report::BuildReport,
+    cache: Option<&cache::Cache>,
+    cancel_token: Option<&cancel::CancelToken>,
+) -> Result<(), Error> {
+    let print_diagnostics = |diagnostics: &str| {
+        if diagnostics_to_stderr {
+            eprint!("{}", diagnostics)
+        } else {
+            print!("{}", diagnostics)
+        }
+    };
+    let default_timing: Option<Duration> = if no_timing {
+        Some(Duration::new(0, 0))
+    } else {
+        None
+    };
+    logs::initialize(&build_state.packages);
+
+    build_report.parsed_modules = build_state
+        .modules
+        .iter()
+        .filter(|(_, module)| is_dirty(module))
+        .map(|(module_name, _)| module_name.to_owned())
+        .collect();
+    build_report.parsed_modules.sort();
+    let num_dirty_modules = build_report.parsed_modules.len() as u64;
 
-    let pb = ProgressBar::new(num_dirty_modules);
+    let pb = if show_progress {
+        ProgressBar::new(num_dirty_modules)
+    } else {
+        ProgressBar::hidden()
+    };
     pb.set_style(
         ProgressStyle::with_template(&format!(
             "{} {} Parsing... {{spinner}} {{pos}}/{{len}} {{msg}}",
@@ -1178,51 +1795,66 @@ pub fn build(filter: &Option<regex::Regex>, path: &str) -> Result<BuildState, ()
     );
 
     let timing_ast = Instant::now();
-    let result_asts = generate_asts(&rescript_version, &mut build_state, &pb);
+    let result_asts = generate_asts(rescript_version, build_state, &pb);
     let timing_ast_elapsed = timing_ast.elapsed();
+    build_report.timings.parse = Some(default_timing.unwrap_or(timing_ast_elapsed).as_secs_f64());
 
     match result_asts {
         Ok(err) => {
-            println!(
-                "{}\r{} {}Parsed {} source files in {:.2}s",
-                LINE_CLEAR,
-                style("[4/6]").bold().dim(),
-                CHECKMARK,
-                num_dirty_modules,
-                timing_ast_elapsed.as_secs_f64()
-            );
-            println!("{}", &err);
+            if show_progress {
+                println!(
+                    "{}\r{} {}Parsed {} source files in {:.2}s",
+                    LINE_CLEAR,
+                    style("[4/6]").bold().dim(),
+                    CHECKMARK,
+                    num_dirty_modules,
+                    default_timing.unwrap_or(timing_ast_elapsed).as_secs_f64()
+                );
+            }
+            print_diagnostics(&format!("{}\n", &err));
         }
         Err(err) => {
             logs::finalize(&build_state.packages);
-            println!(
-                "{}\r{} {}Error parsing source files in {:.2}s",
-                LINE_CLEAR,
-                style("[4/6]").bold().dim(),
-                CROSS,
-                timing_ast_elapsed.as_secs_f64()
-            );
-            println!("{}", &err);
-            clean::cleanup_after_build(&build_state);
-            return Err(());
+            if show_progress {
+                println!(
+                    "{}\r{} {}Error parsing source files in {:.2}s",
+                    LINE_CLEAR,
+                    style("[4/6]").bold().dim(),
+                    CROSS,
+                    default_timing.unwrap_or(timing_ast_elapsed).as_secs_f64()
+                );
+            }
+            print_diagnostics(&format!("{}\n", &err));
+            clean::cleanup_after_build(build_state);
+            state::write(build_state, rescript_version);
+            build_report.add_modules(build_state);
+            return Err(Error::Parse);
         }
     }
 
     let timing_deps = Instant::now();
-    get_deps(&mut build_state, &deleted_module_names);
+    if let Err(e) = get_deps(build_state, deleted_module_names) {
+        // the ASTs are up to date, so they don't need to be generated again in the next build
+        logs::finalize(&build_state.packages);
+        clean::cleanup_after_build(build_state);
+        state::write(build_state, rescript_version);
+        return Err(e);
+    }
     let timing_deps_elapsed = timing_deps.elapsed();
+    build_report.timings.deps = Some(default_timing.unwrap_or(timing_deps_elapsed).as_secs_f64());
 
-    println!(
-        "{}\r{} {}Collected deps in {:.2}s",
-        LINE_CLEAR,
-        style("[5/6]").bold().dim(),
-        CHECKMARK,
-        timing_deps_elapsed.as_secs_f64()
-    );
+    if show_progress {
+        println!(
+            "{}\r{} {}Collected deps in {:.2}s",
+            LINE_CLEAR,
+            style("[5/6]").bold().dim(),
+            CHECKMARK,
+            default_timing.unwrap_or(timing_deps_elapsed).as_secs_f64()
+        );
+    }
 
     let start_compiling = Instant::now();
 
-    let mut compiled_modules = AHashSet::<String>::new();
     let dirty_modules = build_state
         .modules
         .iter()
@@ -1235,26 +1867,6 @@ pub fn build(filter: &Option<regex::Regex>, path: &str) -> Result<BuildState, ()
         })
         .collect::<AHashSet<String>>();
 
-    // for sure clean modules -- after checking the hash of the cmi
-    let mut clean_modules = AHashSet::<String>::new();
-
-    // TODO: calculate the real dirty modules from the orginal dirty modules in each iteration
-    // taken into account the modules that we know are clean, so they don't propagate through the
-    // deps graph
-    // create a hashset of all clean modules form the file-hashes
-    let mut loop_count = 0;
-    let mut files_total_count = compiled_modules.len();
-    let mut files_current_loop_count;
-    let mut compile_errors = "".to_string();
-    let mut compile_warnings = "".to_string();
-    let mut num_compiled_modules = 0;
-    let mut sorted_modules = build_state.module_names.iter().collect::<Vec<&String>>();
-    sorted_modules.sort();
-
-    // for module in dirty_modules.clone() {
-    //     println!("dirty module: {}", module);
-    // }
-
     // this is the whole "compile universe" all modules that might be dirty
     // we get this by traversing from the dirty modules to all the modules that
     // are dependent on them
@@ -1282,7 +1894,11 @@ pub fn build(filter: &Option<regex::Regex>, path: &str) -> Result<BuildState, ()
             break;
         }
     }
-    let pb = ProgressBar::new(compile_universe.len().try_into().unwrap());
+    let pb = if show_progress {
+        ProgressBar::new(compile_universe.len().try_into().unwrap())
+    } else {
+        ProgressBar::hidden()
+    };
     pb.set_style(
         ProgressStyle::with_template(&format!(
             "{} {} Compiling... {{spinner}} {{pos}}/{{len}} {{msg}}",
@@ -1291,291 +1907,244 @@ pub fn build(filter: &Option<regex::Regex>, path: &str) -> Result<BuildState, ()
         ))
         .unwrap(),
     );
-    let compile_universe_count = compile_universe.len();
-
-    // start off with all modules that have no deps in this compile universe
-    let mut in_progress_modules = compile_universe
-        .iter()
-        .filter(|module_name| {
-            let module = build_state.get_module(*module_name).unwrap();
-            module.deps.intersection(&compile_universe).count() == 0
-        })
-        .map(|module_name| module_name.to_string())
-        .collect::<AHashSet<String>>();
 
-    loop {
-        files_current_loop_count = 0;
-        loop_count += 1;
-
-        info!(
-            "Compiled: {} out of {}. Compile loop: {}",
-            files_total_count,
-            compile_universe.len(),
-            loop_count,
-        );
-
-        in_progress_modules
-            .clone()
-            .par_iter()
-            .map(|module_name| {
-                let module = build_state.get_module(module_name).unwrap();
-                let package = build_state
-                    .get_package(&module.package_name)
-                    .expect("Package not found");
-                // all dependencies that we care about are compiled
-                if module
-                    .deps
-                    .intersection(&compile_universe)
-                    .all(|dep| compiled_modules.contains(dep))
-                {
-                    if !module.compile_dirty {
-                        // we are sure we don't have to compile this, so we can mark it as compiled and clean
-                        return Some((
-                            module_name.to_string(),
-                            Ok(None),
-                            Some(Ok(None)),
-                            true,
-                            false,
-                        ));
-                    }
-                    match module.source_type.to_owned() {
-                        SourceType::MlMap(_) => {
-                            // the mlmap needs to be compiled before the files are compiled
-                            // in the same namespace, otherwise we get a compile error
-                            // this is why mlmap is compiled in the AST generation stage
-                            // compile_mlmap(&module.package, module_name, &project_root);
-                            Some((
-                                package.namespace.to_suffix().unwrap(),
-                                Ok(None),
-                                Some(Ok(None)),
-                                false,
-                                false,
-                            ))
-                        }
-                        SourceType::SourceFile(source_file) => {
-                            let cmi_path = helpers::get_compiler_asset(
-                                &source_file.implementation.path,
-                                &module.package_name,
-                                &package.namespace,
-                                &build_state.project_root,
-                                "cmi",
-                            );
-
-                            let cmi_digest = compute_file_hash(&cmi_path);
-
-                            let package = build_state
-                                .get_package(&module.package_name)
-                                .expect("Package not found");
-
-                            let root_package = build_state
-                                .get_package(&build_state.root_config_name)
-                                .unwrap();
-
-                            let interface_result = match source_file.interface.to_owned() {
-                                Some(Interface { path, .. }) => {
-                                    let result = compile_file(
-                                        &package,
-                                        &root_package,
-                                        &helpers::get_iast_path(
-                                            &path,
-                                            &package.name,
-                                            &build_state.project_root,
-                                        ),
-                                        module,
-                                        &build_state.project_root,
-                                        &rescript_version,
-                                        true,
-                                    );
-                                    Some(result)
-                                }
-                                _ => None,
-                            };
-                            let result = compile_file(
-                                &package,
-                                &root_package,
-                                &helpers::get_ast_path(
-                                    &source_file.implementation.path,
-                                    &package.name,
-                                    &build_state.project_root,
-                                ),
-                                module,
-                                &build_state.project_root,
-                                &rescript_version,
-                                false,
-                            );
-                            // if let Err(error) = result.to_owned() {
-                            //     println!("{}", error);
-                            //     panic!("Implementation compilation error!");
-                            // }
-                            let cmi_digest_after = compute_file_hash(&cmi_path);
-
-                            // we want to compare both the hash of interface and the implementation
-                            // compile assets to verify that nothing changed. We also need to checke the interface
-                            // because we can include MyModule, so the modules that depend on this module might
-                            // change when this modules interface does not change, but the implementation does
-                            let is_clean_cmi = match (cmi_digest, cmi_digest_after) {
-                                (Some(cmi_digest), Some(cmi_digest_after)) => {
-                                    cmi_digest.eq(&cmi_digest_after)
-                                }
-
-                                _ => false,
-                            };
+    // modules get compiled as soon as all their dependencies in the compile universe are
+    // compiled, modules that turn out to be clean (because the cmi of their dependencies did not
+    // change) are skipped. The timings of the previous build are used to start the longest chains
+    // first
+    let previous_timings = timings::read(&build_state.project_root);
+    let scheduler::SchedulerResult {
+        outcomes,
+        dirty_modules,
+        unfinished_modules,
+        stats,
+    } = scheduler::run(
+        &build_state.modules,
+        &compile_universe,
+        &previous_timings,
+        rayon::current_num_threads(),
+        cancel_token,
+        |module_name| {
+            compile_module(
+                build_state,
+                module_name,
+                rescript_version,
+                cache,
+                cancel_token,
+            )
+        },
+        |_outcome| {
+            if !(log_enabled!(Info)) {
+                pb.inc(1);
+            }
+        },
+    );
 
-                            Some((
-                                module_name.to_string(),
-                                result,
-                                interface_result,
-                                is_clean_cmi,
-                                true,
-                            ))
-                        }
-                    }
-                } else {
-                    None
-                }
-                .map(|res| {
-                    if !(log_enabled!(Info)) {
-                        pb.inc(1);
-                    }
-                    res
-                })
-            })
-            .collect::<Vec<
-                Option<(
-                    String,
-                    Result<Option<String>, String>,
-                    Option<Result<Option<String>, String>>,
-                    bool,
-                    bool,
-                )>,
-            >>()
+    let num_compiled_modules = stats.jobs;
+    build_report.set_scheduler_stats(&stats);
+    build_report.compiled_modules = outcomes
+        .iter()
+        .filter(|outcome| outcome.is_compiled)
+        .map(|outcome| outcome.module_name.to_owned())
+        .collect();
+    build_report.compiled_modules.sort();
+    build_report.restored_modules = outcomes
+        .iter()
+        .filter(|outcome| outcome.is_restored)
+        .map(|outcome| outcome.module_name.to_owned())
+        .collect();
+    build_report.restored_modules.sort();
+    // modules that failed, or that didn't get compiled because of a failure, still need to be
+    // compiled in the next build
+    dirty_modules.iter().for_each(|module_name| {
+        if let Some(module) = build_state.modules.get_mut(module_name) {
+            module.compile_dirty = true;
+        }
+    });
+    // the errors and durations of the killed compile jobs are not real, so only the jobs that
+    // finished are marked as compiled, the next build compiles the others again
+    if cancel::is_cancelled(cancel_token) {
+        outcomes
             .iter()
-            .for_each(|result| match result {
-                Some((module_name, result, interface_result, is_clean, is_compiled)) => {
-                    in_progress_modules.remove(module_name);
-
-                    if *is_compiled {
-                        num_compiled_modules += 1;
-                    }
-
-                    files_current_loop_count += 1;
-                    compiled_modules.insert(module_name.to_string());
-
-                    if *is_clean {
-                        // actually add it to a list of clean modules
-                        clean_modules.insert(module_name.to_string());
-                    }
-
-                    let module_reverse_deps = build_state
-                        .get_module(module_name)
-                        .unwrap()
-                        .reverse_deps
-                        .clone();
-
-                    // if not clean -- compile modules that depend on this module
-                    for dep in module_reverse_deps.iter() {
-                        let dep_module = build_state.modules.get_mut(dep).unwrap();
-                        //  mark the reverse dep as dirty when the source is not clean
-                        if !*is_clean {
-                            dep_module.compile_dirty = true;
-                        }
-                        if !compiled_modules.contains(dep) {
-                            in_progress_modules.insert(dep.to_string());
-                        }
-                    }
-
-                    let module = build_state.modules.get_mut(module_name).unwrap();
-                    let package = build_state
-                        .packages
-                        .get(&module.package_name)
-                        .expect("Package not found");
-                    match module.source_type {
-                        SourceType::MlMap(_) => (),
-                        SourceType::SourceFile(ref mut source_file) => {
-                            match result {
-                                Ok(Some(err)) => {
-                                    source_file.implementation.compile_state =
-                                        CompileState::Warning;
-                                    logs::append(&package.package_dir, &err);
-                                    compile_warnings.push_str(&err);
-                                }
-                                Ok(None) => (),
-                                Err(err) => {
-                                    source_file.implementation.compile_state = CompileState::Error;
-                                    logs::append(&package.package_dir, &err);
-                                    compile_errors.push_str(&err);
-                                }
-                            };
-                            match interface_result {
-                                Some(Ok(Some(err))) => {
-                                    source_file.interface.as_mut().unwrap().compile_state =
-                                        CompileState::Warning;
-                                    logs::append(&package.package_dir, &err);
-                                    compile_warnings.push_str(&err);
-                                }
-                                Some(Ok(None)) => (),
-                                Some(Err(err)) => {
-                                    source_file.interface.as_mut().unwrap().compile_state =
-                                        CompileState::Error;
-                                    logs::append(&package.package_dir, &err);
-                                    compile_errors.push_str(&err);
-                                }
-                                _ => (),
-                            };
-                        }
-                    }
+            .filter(|outcome| !outcome.has_error())
+            .for_each(|outcome| {
+                if let Some(module) = build_state.modules.get_mut(&outcome.module_name) {
+                    module.compile_dirty = false;
                 }
-                None => (),
             });
-
-        files_total_count += files_current_loop_count;
-
-        if files_total_count == compile_universe_count {
-            break;
-        }
-        if in_progress_modules.len() == 0 {
-            // we probably want to find the cycle(s), and give a helpful error message here
-            compile_errors.push_str("Can't continue... Dependency cycle\n")
+        logs::finalize(&build_state.packages);
+        pb.finish_and_clear();
+        return Err(Error::Cancelled);
+    }
+    timings::write(
+        &build_state.project_root,
+        previous_timings,
+        &outcomes,
+        |module_name| build_state.modules.contains_key(module_name),
+    );
+    outcomes.iter().for_each(|outcome| {
+        if let Some(module) = build_state.modules.get_mut(&outcome.module_name) {
+            module.compile_dirty = outcome.has_error();
         }
-        if compile_errors.len() > 0 {
-            break;
+    });
+
+    // the warnings and errors of all the compiled modules, with the package they belong to. The
+    // modules whose compile job panicked are not compiled, but have an error to show
+    let mut compile_diagnostics: Vec<(String, diagnostics::Diagnostic)> = vec![];
+    for outcome in outcomes
+        .iter()
+        .filter(|outcome| outcome.is_compiled || outcome.is_restored || outcome.has_error())
+    {
+        let module = build_state.modules.get_mut(&outcome.module_name).unwrap();
+        let package = build_state
+            .packages
+            .get(&module.package_name)
+            .expect("Package not found");
+        let mut report = |result: &Result<Option<String>, String>, file: &str| {
+            let (state, diagnostics) = match result {
+                Ok(Some(err)) => (
+                    CompileState::Warning,
+                    diagnostics::parse(err, file, Severity::Warning),
+                ),
+                Ok(None) => (CompileState::Success, vec![]),
+                Err(err) => (
+                    CompileState::Error,
+                    diagnostics::parse(err, file, Severity::Error),
+                ),
+            };
+            if !diagnostics.is_empty() {
+                logs::append(&package.package_dir, &diagnostics::render(&diagnostics));
+            }
+            compile_diagnostics.extend(
+                diagnostics
+                    .iter()
+                    .map(|diagnostic| (package.name.to_owned(), diagnostic.to_owned())),
+            );
+            (state, diagnostics)
         };
+        match module.source_type {
+            SourceType::MlMap(_) => (),
+            SourceType::SourceFile(ref mut source_file) => {
+                let implementation = &mut source_file.implementation;
+                (
+                    implementation.compile_state,
+                    implementation.compile_diagnostics,
+                ) = report(&outcome.result, &implementation.path);
+                if let (Some(result), Some(interface)) = (
+                    outcome.interface_result.as_ref(),
+                    source_file.interface.as_mut(),
+                ) {
+                    (interface.compile_state, interface.compile_diagnostics) =
+                        report(result, &interface.path);
+                }
+            }
+        }
     }
+    let has_compile_errors = outcomes.iter().any(|outcome| outcome.has_error());
+    let render_compile_diagnostics = |severity: Severity| {
+        diagnostics::render(
+            compile_diagnostics
+                .iter()
+                .filter(|(_, diagnostic)| diagnostic.severity == severity)
+                .map(|(_, diagnostic)| diagnostic),
+        )
+    };
+    let compile_warnings = render_compile_diagnostics(Severity::Warning);
+    let compile_errors = render_compile_diagnostics(Severity::Error);
+    let compile_summary = diagnostics::summarize(
+        compile_diagnostics
+            .iter()
+            .map(|(package_name, diagnostic)| (package_name.as_str(), diagnostic)),
+    );
+
+    let cycle_error = if !has_compile_errors && !unfinished_modules.is_empty() {
+        // get_deps already stops the build when there is a cycle, so the modules that are left
+        // should not happen, but if they do we still show what they are waiting for
+        let cycles = cycles::find(
+            build_state
+                .modules
+                .iter()
+                .filter(|(module_name, _)| unfinished_modules.contains(*module_name))
+                .map(|(module_name, module)| (module_name, &module.deps)),
+        );
+        Some(get_cycle_error(build_state, cycles))
+    } else {
+        None
+    };
     let compile_duration = start_compiling.elapsed();
+    build_report.timings.compile = Some(default_timing.unwrap_or(compile_duration).as_secs_f64());
 
     logs::finalize(&build_state.packages);
     pb.finish();
-    clean::cleanup_after_build(&build_state);
-    if compile_errors.len() > 0 {
+    clean::cleanup_after_build(build_state);
+    state::write(build_state, rescript_version);
+    build_report.add_modules(build_state);
+    if has_compile_errors || cycle_error.is_some() {
         if helpers::contains_ascii_characters(&compile_warnings) {
-            println!("{}", &compile_warnings);
+            print_diagnostics(&format!("{}\n", &compile_warnings));
         }
-        println!("{}", &compile_errors);
-        println!(
-            "{}\r{} {}Compiled {} modules in {:.2}s",
-            LINE_CLEAR,
-            style("[6/6]").bold().dim(),
-            CROSS,
-            num_compiled_modules,
-            compile_duration.as_secs_f64()
-        );
-        return Err(());
+        print_diagnostics(&format!("{}\n", &compile_errors));
+        print_diagnostics(&compile_summary);
+        if show_progress {
+            println!(
+                "{}\r{} {}Compiled {} modules in {:.2}s",
+                LINE_CLEAR,
+                style("[6/6]").bold().dim(),
+                CROSS,
+                num_compiled_modules,
+                default_timing.unwrap_or(compile_duration).as_secs_f64()
+            );
+        }
+        return Err(cycle_error.unwrap_or(Error::Compile));
     } else {
         if helpers::contains_ascii_characters(&compile_warnings) {
-            println!("{}", &compile_warnings);
+            print_diagnostics(&format!("{}\n", &compile_warnings));
+            print_diagnostics(&compile_summary);
+        }
+        if show_progress {
+            println!(
+                "{}\r{} {}Compiled {} modules in {:.2}s",
+                LINE_CLEAR,
+                style("[6/6]").bold().dim(),
+                CHECKMARK,
+                num_compiled_modules,
+                default_timing.unwrap_or(compile_duration).as_secs_f64()
+            );
         }
-        println!(
-            "{}\r{} {}Compiled {} modules in {:.2}s",
-            LINE_CLEAR,
-            style("[6/6]").bold().dim(),
-            CHECKMARK,
-            num_compiled_modules,
-            compile_duration.as_secs_f64()
-        );
     }
 
-    let timing_total_elapsed = timing_total.elapsed();
-    println!("Done in {:.2}s", timing_total_elapsed.as_secs_f64());
+    Ok(())
+}
 
-    Ok(build_state)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_package_output_args() {
+        let bsconfig = serde_json::from_str(
+            r#"{
+                "name": "test",
+                "sources": "src",
+                "package-specs": [
+                    {"module": "es6", "in-source": true},
+                    "commonjs",
+                    {"module": "es6-global", "suffix": ".bs.js"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let package = package_tree::make_package(bsconfig, "/project", false, true);
+        assert_eq!(
+            get_package_output_args("/project/src/nested/App.res", &package, &package),
+            vec![
+                "-bs-package-output",
+                "es6:src/nested:.mjs",
+                "-bs-package-output",
+                "commonjs:lib/js/src/nested:.mjs",
+                "-bs-package-output",
+                "es6-global:lib/es6_global/src/nested:.bs.js",
+            ]
+        );
+    }
 }
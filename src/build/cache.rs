@@ -0,0 +1,187 @@
+//! A local, content-addressed cache of compiler outputs.
+//!
+//! When there is a cache directory (`--cache-dir`, or `REWATCH_CACHE_DIR`, for example
+//! `~/.cache/rewatch`), the outputs of every successful compile are stored in that directory, keyed
+//! by a hash of the AST, the compiler version and arguments, and the cmi digests of the modules it
+//! depends on. When a file needs to be compiled and the key is already in the cache, the outputs
+//! are copied back instead of calling bsc. This makes switching back and forth between branches
+//! cheap.
+//!
+//! The cache is shared between the branches of a checkout, not between checkouts at different
+//! locations. The sources are parsed with `-absname`, so the ASTs, and the cmi and cmt files
+//! compiled from them, contain the absolute paths of the sources. Outputs restored at another
+//! location would point the errors of dependent modules and the editor tooling at the other
+//! checkout, so the keys include those paths. Worktrees can use the same directory, but each of
+//! them populates its own entries.
+
+use log::debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CACHE_DIR_ENV: &str = "REWATCH_CACHE_DIR";
+
+/// The name of the file in an entry that holds the output of bsc on stderr, so warnings are
+/// reported again when the entry is restored.
+const STDERR_FILE: &str = "stderr";
+
+/// Part of every key, so entries with another layout are never looked up
+const LAYOUT_VERSION: &str = "2";
+
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+/// Everything (apart from the AST and the arguments) a compile depends on.
+pub struct CacheInputs<'a> {
+    pub cache: &'a Cache,
+    /// the digests of the cmi files of the direct dependencies of the module
+    pub dependency_digests: Vec<String>,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The cache configured with `REWATCH_CACHE_DIR`, if any.
+    pub fn from_env() -> Option<Self> {
+        std::env::var_os(CACHE_DIR_ENV)
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| Self::new(PathBuf::from(dir)))
+    }
+
+    fn get_entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(&key[..2]).join(key)
+    }
+
+    /// The file in an entry for the output at `index`. Outputs can have the same file name, like
+    /// the JavaScript of two out-of-source package specs, so the index keeps them apart.
+    fn get_entry_file(entry: &Path, index: usize, output: &str) -> PathBuf {
+        let file_name = Path::new(output)
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .unwrap_or_default();
+        entry.join(format!("{}-{}", index, file_name))
+    }
+
+    /// Copies the outputs stored under `key` to their locations. Returns the output of bsc on
+    /// stderr when the entry was found, `None` when the file still needs to be compiled.
+    pub fn restore(&self, key: &str, outputs: &[String]) -> Option<String> {
+        let entry = self.get_entry_path(key);
+        let stderr = fs::read_to_string(entry.join(STDERR_FILE)).ok()?;
+        for (index, output) in outputs.iter().enumerate() {
+            if let Err(e) = fs::copy(Self::get_entry_file(&entry, index, output), output) {
+                debug!("Could not restore {} from the cache: {}", output, e);
+                return None;
+            }
+        }
+        Some(stderr)
+    }
+
+    /// Stores the outputs of a compile under `key`. Entries are written to a temporary directory
+    /// first, so other builds never see a partially written entry.
+    pub fn store(&self, key: &str, outputs: &[String], stderr: &str) {
+        let entry = self.get_entry_path(key);
+        if entry.exists() {
+            return;
+        }
+        let tmp = self
+            .dir
+            .join("tmp")
+            .join(format!("{}-{}", key, std::process::id()));
+        let result = fs::create_dir_all(&tmp)
+            .and_then(|_| {
+                outputs.iter().enumerate().try_for_each(|(index, output)| {
+                    fs::copy(output, Self::get_entry_file(&tmp, index, output)).map(|_| ())
+                })
+            })
+            .and_then(|_| fs::write(tmp.join(STDERR_FILE), stderr))
+            .and_then(|_| fs::create_dir_all(entry.parent().unwrap()))
+            .and_then(|_| fs::rename(&tmp, &entry));
+        if let Err(e) = result {
+            debug!("Could not store {} in the cache: {}", key, e);
+            let _ = fs::remove_dir_all(&tmp);
+        }
+    }
+}
+
+/// The key of a compile in the cache. Returns `None` when the AST can't be read.
+pub fn get_key(
+    version: &str,
+    args: &[String],
+    ast_path: &str,
+    dependency_digests: &[String],
+) -> Option<String> {
+    let ast = fs::read(ast_path).ok()?;
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(LAYOUT_VERSION.as_bytes());
+    hasher.update(b"\0");
+    hasher.update(version.as_bytes());
+    for arg in args {
+        hasher.update(b"\0");
+        hasher.update(arg.as_bytes());
+    }
+    hasher.update(b"\0");
+    hasher.update(&ast);
+    for digest in dependency_digests {
+        hasher.update(b"\0");
+        hasher.update(digest.as_bytes());
+    }
+    Some(hasher.finalize().to_hex().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restores_stored_outputs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let cache = Cache::new(dir.join("cache"));
+        let output = dir.join("out").join("Foo.cmj");
+        fs::create_dir_all(output.parent().unwrap()).unwrap();
+        let outputs = vec![output.to_str().unwrap().to_string()];
+
+        let key = blake3::hash(b"key").to_hex().to_string();
+        assert_eq!(cache.restore(&key, &outputs), None);
+
+        fs::write(&output, "compiled").unwrap();
+        cache.store(&key, &outputs, "warning");
+        fs::remove_file(&output).unwrap();
+
+        assert_eq!(cache.restore(&key, &outputs), Some("warning".to_string()));
+        assert_eq!(fs::read_to_string(&output).unwrap(), "compiled");
+    }
+
+    #[test]
+    fn test_keeps_outputs_with_the_same_name_apart() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let cache = Cache::new(dir.join("cache"));
+        // the JavaScript of a commonjs and an es6 package spec
+        let outputs = ["lib/js/src/A.js", "lib/es6/src/A.js"]
+            .iter()
+            .map(|output| dir.join(output))
+            .collect::<Vec<PathBuf>>();
+        for (output, contents) in outputs.iter().zip(["commonjs", "es6"]) {
+            fs::create_dir_all(output.parent().unwrap()).unwrap();
+            fs::write(output, contents).unwrap();
+        }
+        let outputs = outputs
+            .iter()
+            .map(|output| output.to_str().unwrap().to_string())
+            .collect::<Vec<String>>();
+
+        let key = blake3::hash(b"key").to_hex().to_string();
+        cache.store(&key, &outputs, "");
+        outputs
+            .iter()
+            .for_each(|output| fs::remove_file(output).unwrap());
+
+        assert_eq!(cache.restore(&key, &outputs), Some("".to_string()));
+        assert_eq!(fs::read_to_string(&outputs[0]).unwrap(), "commonjs");
+        assert_eq!(fs::read_to_string(&outputs[1]).unwrap(), "es6");
+    }
+}
@@ -0,0 +1,113 @@
+//! Stopping a build that is in progress. The watcher cancels the build when files change while it
+//! is compiling, the compile jobs that didn't start yet are not started anymore, and the bsc
+//! processes that are running get killed.
+
+use std::io::{self, Read};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often a running bsc process checks whether the build got cancelled
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A flag that is shared between the watcher and the build it started.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst)
+    }
+
+    /// Makes the token usable for the next build
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+pub fn is_cancelled(token: Option<&CancelToken>) -> bool {
+    token.map(CancelToken::is_cancelled).unwrap_or(false)
+}
+
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buffer = vec![];
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buffer);
+        }
+        buffer
+    })
+}
+
+fn wait(mut child: Child, token: &CancelToken) -> io::Result<Output> {
+    // the pipes are read on their own threads, so the process doesn't block on a full pipe
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if token.is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "the build was cancelled",
+            ));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Runs the command like `Command::output`, but kills the process when the token gets cancelled,
+/// that is reported as an error of the kind `Interrupted`.
+pub fn output(command: &mut Command, token: Option<&CancelToken>) -> io::Result<Output> {
+    match token {
+        None => command.output(),
+        Some(token) => wait(
+            command
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?,
+            token,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_kills_the_process_when_cancelled() {
+        let token = CancelToken::new();
+        let output = output(Command::new("echo").arg("hello"), Some(&token)).unwrap();
+        assert_eq!(output.stdout, b"hello\n");
+
+        let cancel = token.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            cancel.cancel();
+        });
+        let start = std::time::Instant::now();
+        let result = super::output(Command::new("sleep").arg("10"), Some(&token));
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
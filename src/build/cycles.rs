@@ -0,0 +1,168 @@
+//! Finds the cycles in a dependency graph, of modules or of packages, so we can tell the user which
+//! dependencies to break up, instead of only that the build can't continue.
+
+use ahash::{AHashMap, AHashSet};
+use std::collections::VecDeque;
+
+/// The strongly connected components of the graph with more than one node (self-dependencies are
+/// ignored), using an iterative version of Tarjan's algorithm, so deep graphs don't overflow
+/// the stack.
+fn get_components(edges: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut index: Vec<Option<usize>> = vec![None; edges.len()];
+    let mut lowlink = vec![0; edges.len()];
+    let mut on_stack = vec![false; edges.len()];
+    let mut stack = vec![];
+    let mut next_index = 0;
+    let mut components = vec![];
+
+    for start in 0..edges.len() {
+        if index[start].is_some() {
+            continue;
+        }
+        let mut call_stack = vec![(start, 0)];
+        index[start] = Some(next_index);
+        lowlink[start] = next_index;
+        next_index += 1;
+        stack.push(start);
+        on_stack[start] = true;
+
+        while let Some((node, next_edge)) = call_stack.last_mut() {
+            let node = *node;
+            if let Some(&dep) = edges[node].get(*next_edge) {
+                *next_edge += 1;
+                match index[dep] {
+                    None => {
+                        index[dep] = Some(next_index);
+                        lowlink[dep] = next_index;
+                        next_index += 1;
+                        stack.push(dep);
+                        on_stack[dep] = true;
+                        call_stack.push((dep, 0));
+                    }
+                    Some(dep_index) if on_stack[dep] => {
+                        lowlink[node] = lowlink[node].min(dep_index);
+                    }
+                    Some(_) => (),
+                }
+            } else {
+                call_stack.pop();
+                if let Some((parent, _)) = call_stack.last() {
+                    lowlink[*parent] = lowlink[*parent].min(lowlink[node]);
+                }
+                if Some(lowlink[node]) == index[node] {
+                    let mut component = vec![];
+                    while let Some(member) = stack.pop() {
+                        on_stack[member] = false;
+                        component.push(member);
+                        if member == node {
+                            break;
+                        }
+                    }
+                    if component.len() > 1 {
+                        components.push(component);
+                    }
+                }
+            }
+        }
+    }
+    components
+}
+
+/// The shortest path from `start` back to itself, through the nodes of its component.
+fn get_cycle(start: usize, component: &AHashSet<usize>, edges: &[Vec<usize>]) -> Vec<usize> {
+    let mut previous: AHashMap<usize, usize> = AHashMap::new();
+    let mut queue = VecDeque::from([start]);
+    while let Some(node) = queue.pop_front() {
+        for &dep in edges[node].iter().filter(|dep| component.contains(dep)) {
+            if dep == start {
+                let mut cycle = vec![start, node];
+                let mut current = node;
+                while let Some(&before) = previous.get(&current) {
+                    cycle.push(before);
+                    current = before;
+                }
+                cycle.reverse();
+                return cycle;
+            }
+            if !previous.contains_key(&dep) {
+                previous.insert(dep, node);
+                queue.push_back(dep);
+            }
+        }
+    }
+    unreachable!("A strongly connected component always has a cycle")
+}
+
+/// Returns a cycle for every group of nodes that depend on each other, as the names of the nodes
+/// from the first node back to itself (`["A", "B", "C", "A"]`). Dependencies on nodes that are not
+/// in the graph are ignored.
+pub fn find<'a>(
+    graph: impl IntoIterator<Item = (&'a String, &'a AHashSet<String>)>,
+) -> Vec<Vec<String>> {
+    let mut graph = graph.into_iter().collect::<Vec<_>>();
+    graph.sort_by_key(|(name, _)| *name);
+    let names = graph
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<&String>>();
+    let indices = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (*name, i))
+        .collect::<AHashMap<&String, usize>>();
+    let edges = graph
+        .iter()
+        .map(|(_, deps)| {
+            let mut edges = deps
+                .iter()
+                .filter_map(|dep| indices.get(dep).copied())
+                .collect::<Vec<usize>>();
+            edges.sort();
+            edges
+        })
+        .collect::<Vec<Vec<usize>>>();
+
+    let mut cycles = get_components(&edges)
+        .into_iter()
+        .map(|component| {
+            let start = *component.iter().min().unwrap();
+            get_cycle(start, &component.into_iter().collect(), &edges)
+                .into_iter()
+                .map(|i| names[i].to_owned())
+                .collect::<Vec<String>>()
+        })
+        .collect::<Vec<Vec<String>>>();
+    cycles.sort();
+    cycles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finds_the_shortest_cycle_of_every_component() {
+        let graph = [
+            ("A", vec!["B"]),
+            ("B", vec!["C", "D"]),
+            ("C", vec!["A"]),
+            ("D", vec!["B", "E", "Missing"]),
+            ("E", vec![]),
+            ("X", vec!["Y"]),
+            ("Y", vec!["X", "E"]),
+        ]
+        .into_iter()
+        .map(|(name, deps)| {
+            (
+                name.to_string(),
+                deps.into_iter().map(|dep| dep.to_string()).collect(),
+            )
+        })
+        .collect::<AHashMap<String, AHashSet<String>>>();
+
+        assert_eq!(
+            find(graph.iter()),
+            vec![vec!["A", "B", "C", "A"], vec!["X", "Y", "X"]]
+        );
+    }
+}
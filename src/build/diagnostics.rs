@@ -0,0 +1,281 @@
+//! Warnings and errors of the compiler, parsed from what bsc prints on stderr.
+//!
+//! bsc prints every diagnostic as a block that starts with a header ("We've found a bug for you!",
+//! "Syntax error!" or "Warning number 27"), followed by the location, a code frame and the
+//! message. We keep the text of the block as it was printed, so it can be shown to the user as-is,
+//! next to the parts that tools need.
+
+use ahash::{AHashMap, AHashSet};
+use regex::Regex;
+use serde::Serialize;
+use std::fmt;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Lines and columns start at 1, the end column is inclusive.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct Range {
+    pub start_line: u32,
+    pub start_column: u32,
+    pub end_line: u32,
+    pub end_column: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct Diagnostic {
+    pub file: String,
+    /// `None` when bsc didn't print a location we understand
+    pub range: Option<Range>,
+    pub severity: Severity,
+    pub warning_number: Option<u32>,
+    pub message: String,
+    /// the diagnostic as bsc printed it
+    pub text: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\n{}\n", self.text)
+    }
+}
+
+fn header_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(
+            r"^(We've found a bug for you!|Syntax error!|Warning number (\d+)( \(configured as error\))?)$",
+        )
+        .unwrap()
+    })
+}
+
+fn location_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    // file:line:column, file:line:column-end_column or file:line:column-end_line:end_column
+    RE.get_or_init(|| Regex::new(r"^(.+):(\d+):(\d+)(?:-(\d+)(?::(\d+))?)?$").unwrap())
+}
+
+fn code_frame_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"^(\d+|\.+) │").unwrap())
+}
+
+fn escape_colours(str: &str) -> String {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"[\u001b\u009b]\[[()#;?]*(?:[0-9]{1,4}(?:;[0-9]{0,4})*)?[0-9A-ORZcf-nqry=><]")
+            .unwrap()
+    })
+    .replace_all(str, "")
+    .to_string()
+}
+
+fn parse_range(captures: &regex::Captures) -> Option<Range> {
+    let number = |i| captures.get(i).and_then(|m| m.as_str().parse::<u32>().ok());
+    let start_line = number(2)?;
+    let start_column = number(3)?;
+    let (end_line, end_column) = match (number(4), number(5)) {
+        (Some(end_line), Some(end_column)) => (end_line, end_column),
+        (Some(end_column), None) => (start_line, end_column),
+        _ => (start_line, start_column),
+    };
+    Some(Range {
+        start_line,
+        start_column,
+        end_line,
+        end_column,
+    })
+}
+
+fn parse_block(lines: &[&str], file: &str, default_severity: Severity) -> Diagnostic {
+    let text = lines.join("\n").trim_end().to_string();
+    let mut rest = lines
+        .iter()
+        .map(|line| line.trim())
+        .skip_while(|line| line.is_empty());
+
+    let (severity, warning_number) = match rest
+        .clone()
+        .next()
+        .and_then(|line| header_regex().captures(line))
+    {
+        Some(captures) => {
+            rest.next();
+            match captures.get(2) {
+                Some(number) => (
+                    if captures.get(3).is_some() {
+                        Severity::Error
+                    } else {
+                        Severity::Warning
+                    },
+                    number.as_str().parse::<u32>().ok(),
+                ),
+                None => (Severity::Error, None),
+            }
+        }
+        None => (default_severity, None),
+    };
+
+    let location = rest
+        .clone()
+        .find(|line| !line.is_empty())
+        .and_then(|line| location_regex().captures(line));
+    let (file, range) = match location {
+        Some(captures) => {
+            rest.find(|line| !line.is_empty());
+            (captures[1].to_string(), parse_range(&captures))
+        }
+        None => (file.to_string(), None),
+    };
+
+    let message = rest
+        .filter(|line| !code_frame_regex().is_match(line))
+        .collect::<Vec<&str>>()
+        .join("\n")
+        .trim()
+        .to_string();
+
+    Diagnostic {
+        file,
+        range,
+        severity,
+        warning_number,
+        message,
+        text,
+    }
+}
+
+/// Parses the output of bsc for `file`. Output that isn't part of a diagnostic we recognize is
+/// attributed to `file`, with `default_severity`.
+pub fn parse(output: &str, file: &str, default_severity: Severity) -> Vec<Diagnostic> {
+    let output = escape_colours(output);
+    let mut blocks: Vec<Vec<&str>> = vec![vec![]];
+    for line in output.lines() {
+        if header_regex().is_match(line.trim()) {
+            blocks.push(vec![]);
+        }
+        blocks.last_mut().unwrap().push(line);
+    }
+    blocks
+        .iter()
+        .filter(|lines| lines.iter().any(|line| !line.trim().is_empty()))
+        .map(|lines| parse_block(lines, file, default_severity))
+        .collect()
+}
+
+/// The diagnostics as they are shown to the user, without duplicates.
+pub fn render<'a>(diagnostics: impl IntoIterator<Item = &'a Diagnostic>) -> String {
+    let mut seen = AHashSet::new();
+    diagnostics
+        .into_iter()
+        .filter(|diagnostic| seen.insert(*diagnostic))
+        .map(|diagnostic| diagnostic.to_string())
+        .collect()
+}
+
+/// A line per package with the number of errors and warnings, for the packages that have any.
+pub fn summarize<'a>(diagnostics: impl IntoIterator<Item = (&'a str, &'a Diagnostic)>) -> String {
+    let mut seen = AHashSet::new();
+    let mut counts: AHashMap<&str, (usize, usize)> = AHashMap::new();
+    diagnostics
+        .into_iter()
+        .filter(|(_, diagnostic)| seen.insert(*diagnostic))
+        .for_each(|(package_name, diagnostic)| {
+            let (errors, warnings) = counts.entry(package_name).or_default();
+            match diagnostic.severity {
+                Severity::Error => *errors += 1,
+                Severity::Warning => *warnings += 1,
+            }
+        });
+    let mut counts = counts.into_iter().collect::<Vec<(&str, (usize, usize))>>();
+    counts.sort();
+    counts
+        .into_iter()
+        .map(|(package_name, (errors, warnings))| {
+            format!(
+                "{}: {} error{}, {} warning{}\n",
+                package_name,
+                errors,
+                if errors == 1 { "" } else { "s" },
+                warnings,
+                if warnings == 1 { "" } else { "s" }
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTPUT: &str = "
+  Warning number 32
+  /repo/src/Foo.res:1:5-10
+
+  1 │ let unused = 1
+  2 │ let x = 2
+
+  unused value unused.
+
+
+  We've found a bug for you!
+  /repo/src/Foo.res:2:9-3:2
+
+  1 │ let unused = 1
+  2 │ let x = \"a\" + 1
+
+  This has type: string
+  Somewhere wanted: int
+";
+
+    #[test]
+    fn test_parses_warnings_and_errors() {
+        let diagnostics = parse(OUTPUT, "/repo/src/Foo.res", Severity::Error);
+        assert_eq!(diagnostics.len(), 2);
+
+        let warning = &diagnostics[0];
+        assert_eq!(warning.severity, Severity::Warning);
+        assert_eq!(warning.warning_number, Some(32));
+        assert_eq!(
+            warning.range,
+            Some(Range {
+                start_line: 1,
+                start_column: 5,
+                end_line: 1,
+                end_column: 10
+            })
+        );
+        assert_eq!(warning.message, "unused value unused.");
+
+        let error = &diagnostics[1];
+        assert_eq!(error.severity, Severity::Error);
+        assert_eq!(error.file, "/repo/src/Foo.res");
+        assert_eq!(
+            error.range.as_ref().map(|r| (r.end_line, r.end_column)),
+            Some((3, 2))
+        );
+        assert_eq!(
+            error.message,
+            "This has type: string\nSomewhere wanted: int"
+        );
+    }
+
+    #[test]
+    fn test_keeps_unrecognized_output() {
+        let diagnostics = parse(
+            "bsc: command not found",
+            "/repo/src/Foo.res",
+            Severity::Error,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].file, "/repo/src/Foo.res");
+        assert_eq!(diagnostics[0].range, None);
+        assert_eq!(diagnostics[0].message, "bsc: command not found");
+    }
+}
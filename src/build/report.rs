@@ -0,0 +1,205 @@
+//! A machine-readable report of a build, for CI dashboards and other tools that would otherwise
+//! have to scrape the `[1/6]`...`[6/6]` lines.
+
+use crate::build::diagnostics::{Diagnostic, Severity};
+use crate::build::scheduler::SchedulerStats;
+use crate::build_types::*;
+use crate::error::Error;
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+
+/// Where the report is written to.
+#[derive(Debug, Clone)]
+pub enum Target {
+    /// Instead of the progress, the report is written to stdout. Warnings and errors of the
+    /// compiler still go to stderr.
+    Stdout,
+    File(PathBuf),
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    #[default]
+    Success,
+    Failed,
+}
+
+/// The time every phase of the build took in seconds, `None` for the phases that didn't run.
+#[derive(Serialize, Debug, Default)]
+pub struct PhaseTimings {
+    pub package_tree: Option<f64>,
+    pub source_files: Option<f64>,
+    pub cleanup: Option<f64>,
+    pub parse: Option<f64>,
+    pub deps: Option<f64>,
+    pub compile: Option<f64>,
+    pub total: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Scheduler {
+    pub workers: usize,
+    pub jobs: usize,
+    pub skipped: usize,
+    pub utilisation: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FileResult {
+    pub path: String,
+    pub parse_state: ParseState,
+    pub compile_state: CompileState,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ModuleResult {
+    pub module_name: String,
+    pub package_name: String,
+    pub implementation: FileResult,
+    pub interface: Option<FileResult>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReportedError {
+    pub message: String,
+    pub exit_code: i32,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct BuildReport {
+    pub status: Status,
+    pub error: Option<ReportedError>,
+    pub rescript_version: Option<String>,
+    pub timings: PhaseTimings,
+    pub scheduler: Option<Scheduler>,
+    pub parsed_modules: Vec<String>,
+    pub compiled_modules: Vec<String>,
+    /// the modules of which the outputs were restored from the cache, instead of compiled
+    pub restored_modules: Vec<String>,
+    pub errors: usize,
+    pub warnings: usize,
+    /// the modules that were parsed, compiled or restored in this build
+    pub modules: Vec<ModuleResult>,
+}
+
+fn file_result(
+    path: &str,
+    parse_state: &ParseState,
+    compile_state: &CompileState,
+    parse_diagnostics: &[Diagnostic],
+    compile_diagnostics: &[Diagnostic],
+) -> FileResult {
+    FileResult {
+        path: path.to_string(),
+        parse_state: parse_state.to_owned(),
+        compile_state: compile_state.to_owned(),
+        diagnostics: [parse_diagnostics, compile_diagnostics].concat(),
+    }
+}
+
+impl BuildReport {
+    pub fn set_scheduler_stats(&mut self, stats: &SchedulerStats) {
+        self.scheduler = Some(Scheduler {
+            workers: stats.workers,
+            jobs: stats.jobs,
+            skipped: stats.skipped,
+            utilisation: stats.utilisation(),
+        });
+    }
+
+    /// Adds the results of the modules that were parsed, compiled or restored in this build.
+    pub fn add_modules(&mut self, build_state: &BuildState) {
+        let mut module_names = self
+            .parsed_modules
+            .iter()
+            .chain(self.compiled_modules.iter())
+            .chain(self.restored_modules.iter())
+            .cloned()
+            .collect::<Vec<String>>();
+        module_names.sort();
+        module_names.dedup();
+
+        self.modules = module_names
+            .iter()
+            .filter_map(|module_name| {
+                let module = build_state.get_module(module_name)?;
+                match &module.source_type {
+                    SourceType::MlMap(_) => None,
+                    SourceType::SourceFile(source_file) => {
+                        let implementation = &source_file.implementation;
+                        Some(ModuleResult {
+                            module_name: module_name.to_owned(),
+                            package_name: module.package_name.to_owned(),
+                            implementation: file_result(
+                                &implementation.path,
+                                &implementation.parse_state,
+                                &implementation.compile_state,
+                                &implementation.parse_diagnostics,
+                                &implementation.compile_diagnostics,
+                            ),
+                            interface: source_file.interface.as_ref().map(|interface| {
+                                file_result(
+                                    &interface.path,
+                                    &interface.parse_state,
+                                    &interface.compile_state,
+                                    &interface.parse_diagnostics,
+                                    &interface.compile_diagnostics,
+                                )
+                            }),
+                        })
+                    }
+                }
+            })
+            .collect();
+
+        let (errors, warnings) =
+            self.diagnostics()
+                .fold((0, 0), |(errors, warnings), diagnostic| {
+                    match diagnostic.severity {
+                        Severity::Error => (errors + 1, warnings),
+                        Severity::Warning => (errors, warnings + 1),
+                    }
+                });
+        self.errors = errors;
+        self.warnings = warnings;
+    }
+
+    /// The warnings and errors of the modules in the report.
+    pub fn diagnostics(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.modules.iter().flat_map(|module| {
+            std::iter::once(&module.implementation)
+                .chain(module.interface.iter())
+                .flat_map(|file| file.diagnostics.iter())
+        })
+    }
+
+    pub fn finish<T>(&mut self, result: &Result<T, Error>) {
+        match result {
+            Ok(_) => self.status = Status::Success,
+            Err(e) => {
+                self.status = Status::Failed;
+                self.error = Some(ReportedError {
+                    message: e.to_string(),
+                    exit_code: e.exit_code(),
+                });
+            }
+        }
+    }
+
+    pub fn write(&self, target: &Target) -> Result<(), Error> {
+        let json = serde_json::to_string_pretty(self).unwrap();
+        match target {
+            Target::Stdout => {
+                println!("{}", json);
+                Ok(())
+            }
+            Target::File(path) => fs::write(path, json + "\n").map_err(|error| Error::Io {
+                context: format!("Could not write the build report to {}", path.display()),
+                error,
+            }),
+        }
+    }
+}
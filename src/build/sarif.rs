@@ -0,0 +1,253 @@
+//! The warnings and errors of a build as a SARIF 2.1.0 log, so code review tools can annotate
+//! pull requests without parsing `.compiler.log`.
+//!
+//! Locations are relative to the project root (the `SRCROOT` base), files outside of it keep their
+//! absolute path. The rule id of a result is the number of the warning, errors don't have one.
+
+use crate::build::diagnostics::{Diagnostic, Severity};
+use crate::error::Error;
+use ahash::{AHashMap, AHashSet};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+const SRCROOT: &str = "SRCROOT";
+
+#[derive(Serialize, Debug)]
+struct Log {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    version: &'static str,
+    runs: Vec<Run>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Run {
+    tool: Tool,
+    original_uri_base_ids: AHashMap<&'static str, ArtifactLocation>,
+    results: Vec<SarifResult>,
+}
+
+#[derive(Serialize, Debug)]
+struct Tool {
+    driver: Driver,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Driver {
+    name: &'static str,
+    version: &'static str,
+    information_uri: &'static str,
+    rules: Vec<Rule>,
+}
+
+#[derive(Serialize, Debug)]
+struct Rule {
+    id: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SarifResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule_id: Option<String>,
+    level: &'static str,
+    message: Message,
+    locations: Vec<Location>,
+}
+
+#[derive(Serialize, Debug)]
+struct Message {
+    text: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Location {
+    physical_location: PhysicalLocation,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PhysicalLocation {
+    artifact_location: ArtifactLocation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    region: Option<Region>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ArtifactLocation {
+    uri: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uri_base_id: Option<&'static str>,
+}
+
+/// Unlike the range of a diagnostic, the end column of a region is exclusive.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Region {
+    start_line: u32,
+    start_column: u32,
+    end_line: u32,
+    end_column: u32,
+}
+
+fn to_uri(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+fn get_artifact_location(file: &str, project_root: &str) -> ArtifactLocation {
+    match Path::new(file).strip_prefix(project_root) {
+        Ok(relative_path) => ArtifactLocation {
+            uri: to_uri(relative_path),
+            uri_base_id: Some(SRCROOT),
+        },
+        Err(_) => ArtifactLocation {
+            uri: format!("file://{}", to_uri(Path::new(file))),
+            uri_base_id: None,
+        },
+    }
+}
+
+fn to_result(diagnostic: &Diagnostic, project_root: &str) -> SarifResult {
+    SarifResult {
+        rule_id: diagnostic.warning_number.map(|number| number.to_string()),
+        level: match diagnostic.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        },
+        message: Message {
+            text: if diagnostic.message.is_empty() {
+                diagnostic.text.trim().to_string()
+            } else {
+                diagnostic.message.to_owned()
+            },
+        },
+        locations: vec![Location {
+            physical_location: PhysicalLocation {
+                artifact_location: get_artifact_location(&diagnostic.file, project_root),
+                region: diagnostic.range.as_ref().map(|range| Region {
+                    start_line: range.start_line,
+                    start_column: range.start_column,
+                    end_line: range.end_line,
+                    end_column: range.end_column + 1,
+                }),
+            },
+        }],
+    }
+}
+
+fn to_log<'a>(diagnostics: impl IntoIterator<Item = &'a Diagnostic>, project_root: &str) -> Log {
+    let mut seen = AHashSet::new();
+    let diagnostics = diagnostics
+        .into_iter()
+        .filter(|diagnostic| seen.insert(*diagnostic))
+        .collect::<Vec<&Diagnostic>>();
+    let rules = diagnostics
+        .iter()
+        .filter_map(|diagnostic| diagnostic.warning_number)
+        .collect::<BTreeSet<u32>>()
+        .into_iter()
+        .map(|number| Rule {
+            id: number.to_string(),
+        })
+        .collect();
+
+    Log {
+        schema: SCHEMA,
+        version: "2.1.0",
+        runs: vec![Run {
+            tool: Tool {
+                driver: Driver {
+                    name: "rewatch",
+                    version: env!("CARGO_PKG_VERSION"),
+                    information_uri: "https://github.com/rolandpeelen/rewatch",
+                    rules,
+                },
+            },
+            original_uri_base_ids: AHashMap::from_iter([(
+                SRCROOT,
+                ArtifactLocation {
+                    uri: format!("file://{}/", to_uri(Path::new(project_root))),
+                    uri_base_id: None,
+                },
+            )]),
+            results: diagnostics
+                .iter()
+                .map(|diagnostic| to_result(diagnostic, project_root))
+                .collect(),
+        }],
+    }
+}
+
+/// Writes the diagnostics as a SARIF log to `path`.
+pub fn write<'a>(
+    path: &Path,
+    diagnostics: impl IntoIterator<Item = &'a Diagnostic>,
+    project_root: &str,
+) -> Result<(), Error> {
+    let log = to_log(diagnostics, project_root);
+    fs::write(path, serde_json::to_string_pretty(&log).unwrap() + "\n").map_err(|error| Error::Io {
+        context: format!("Could not write the SARIF log to {}", path.display()),
+        error,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::diagnostics::Range;
+
+    #[test]
+    fn test_converts_diagnostics_to_results() {
+        let warning = Diagnostic {
+            file: "/repo/src/Foo.res".to_string(),
+            range: Some(Range {
+                start_line: 1,
+                start_column: 5,
+                end_line: 1,
+                end_column: 10,
+            }),
+            severity: Severity::Warning,
+            warning_number: Some(32),
+            message: "unused value unused.".to_string(),
+            text: "".to_string(),
+        };
+        let error = Diagnostic {
+            file: "/elsewhere/Bar.res".to_string(),
+            range: None,
+            severity: Severity::Error,
+            warning_number: None,
+            message: "".to_string(),
+            text: "  Syntax error!".to_string(),
+        };
+        let log = serde_json::to_value(to_log([&warning, &error, &warning], "/repo")).unwrap();
+        let run = &log["runs"][0];
+
+        assert_eq!(run["tool"]["driver"]["rules"][0]["id"], "32");
+        assert_eq!(run["originalUriBaseIds"]["SRCROOT"]["uri"], "file:///repo/");
+        assert_eq!(run["results"].as_array().unwrap().len(), 2);
+
+        let result = &run["results"][0];
+        assert_eq!(result["ruleId"], "32");
+        assert_eq!(result["level"], "warning");
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/Foo.res");
+        assert_eq!(location["artifactLocation"]["uriBaseId"], "SRCROOT");
+        assert_eq!(location["region"]["endColumn"], 11);
+
+        let result = &run["results"][1];
+        assert_eq!(result.get("ruleId"), None);
+        assert_eq!(result["level"], "error");
+        assert_eq!(result["message"]["text"], "Syntax error!");
+        assert_eq!(
+            result["locations"][0]["physicalLocation"]["artifactLocation"]["uri"],
+            "file:///elsewhere/Bar.res"
+        );
+    }
+}
@@ -0,0 +1,695 @@
+//! Dependency driven scheduler for the compile phase.
+//!
+//! Instead of compiling the compile universe in "waves" (where every module of a wave needs to be
+//! finished before any module of the next wave can start), the scheduler keeps track of how many
+//! dependencies of every module are still outstanding. As soon as the last dependency of a module
+//! is finished, the module is handed to the next idle worker. The coordinator runs on the calling
+//! thread, the compile jobs run on a fixed set of worker threads.
+//!
+//! When more modules are ready than there are idle workers, the modules on the critical path go
+//! first: the ones with the longest (estimated) chain of compile work depending on them. The
+//! estimate uses the compile time of every module in the previous build, if it is known.
+
+use crate::build::cancel::{self, CancelToken};
+use crate::build_types::*;
+use ahash::{AHashMap, AHashSet};
+use log::info;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The result of compiling (or skipping) a single module in the compile universe.
+#[derive(Debug, Clone)]
+pub struct CompileOutcome {
+    pub module_name: String,
+    pub result: Result<Option<String>, String>,
+    pub interface_result: Option<Result<Option<String>, String>>,
+    /// the cmi of the module did not change, so modules depending on it don't have to be
+    /// recompiled because of this module
+    pub is_clean: bool,
+    /// bsc was actually invoked for this module
+    pub is_compiled: bool,
+    /// the outputs of the module were restored from the cache, so bsc was not invoked
+    pub is_restored: bool,
+    /// the time the compile job took, filled in by the scheduler
+    pub duration: Duration,
+}
+
+impl CompileOutcome {
+    /// A module that did not need to be compiled at all.
+    pub fn skipped(module_name: &str) -> Self {
+        Self {
+            module_name: module_name.to_string(),
+            result: Ok(None),
+            interface_result: Some(Ok(None)),
+            is_clean: true,
+            is_compiled: false,
+            is_restored: false,
+            duration: Duration::ZERO,
+        }
+    }
+
+    /// A module of which the compile job panicked, the panic is reported as a compile error so the
+    /// build stops like it does for any other error.
+    pub fn panicked(module_name: &str, message: &str) -> Self {
+        Self {
+            module_name: module_name.to_string(),
+            result: Err(format!(
+                "Compiling {} failed unexpectedly: {}",
+                module_name, message
+            )),
+            interface_result: None,
+            is_clean: false,
+            is_compiled: false,
+            is_restored: false,
+            duration: Duration::ZERO,
+        }
+    }
+
+    pub fn has_error(&self) -> bool {
+        self.result.is_err() || matches!(self.interface_result, Some(Err(_)))
+    }
+}
+
+/// Some numbers on how well the scheduler kept the workers busy.
+#[derive(Debug, Clone, Default)]
+pub struct SchedulerStats {
+    pub workers: usize,
+    pub jobs: usize,
+    pub skipped: usize,
+    pub wall_time: Duration,
+    /// The sum of the time every worker was waiting for work, while the compile phase was running
+    pub idle_worker_time: Duration,
+    /// The maximum amount of workers that were waiting for work at the same time, while there
+    /// were still jobs in flight
+    pub max_idle_workers: usize,
+}
+
+impl SchedulerStats {
+    pub fn utilisation(&self) -> f64 {
+        let available = self.wall_time.as_secs_f64() * self.workers as f64;
+        if available == 0.0 {
+            1.0
+        } else {
+            1.0 - (self.idle_worker_time.as_secs_f64() / available).min(1.0)
+        }
+    }
+}
+
+pub struct SchedulerResult {
+    pub outcomes: Vec<CompileOutcome>,
+    /// Modules that got (transitively) dirty during the compile, because one of their
+    /// dependencies got a new cmi
+    pub dirty_modules: AHashSet<String>,
+    /// The modules in the compile universe that never got started, either because of a compile
+    /// error or because of a dependency cycle
+    pub unfinished_modules: AHashSet<String>,
+    pub stats: SchedulerStats,
+}
+
+/// Compiles all modules in `compile_universe`, respecting the `deps` / `reverse_deps` of the
+/// modules. `compile` is called on a worker thread for every module that is dirty, modules that
+/// are not dirty (anymore) when their dependencies are finished, are skipped. `on_finished` is
+/// called on the coordinating thread for every finished module, in the order they finish.
+/// `costs` are the compile times of the modules in a previous build, used for prioritising.
+///
+/// When a module fails to compile, or when the build gets cancelled, no new jobs are started, but
+/// the jobs in flight are finished.
+pub fn run<F, P>(
+    modules: &AHashMap<String, Module>,
+    compile_universe: &AHashSet<String>,
+    costs: &AHashMap<String, Duration>,
+    workers: usize,
+    cancel_token: Option<&CancelToken>,
+    compile: F,
+    mut on_finished: P,
+) -> SchedulerResult
+where
+    F: Fn(&str) -> CompileOutcome + Sync,
+    P: FnMut(&CompileOutcome),
+{
+    let workers = workers.max(1);
+    let start = Instant::now();
+    let mut stats = SchedulerStats {
+        workers,
+        ..SchedulerStats::default()
+    };
+
+    let mut graph = Graph::new(modules, compile_universe, costs);
+    let mut has_error = false;
+
+    let (job_sender, job_receiver) = mpsc::channel::<String>();
+    let job_receiver = Mutex::new(job_receiver);
+    let (result_sender, result_receiver) = mpsc::channel::<CompileOutcome>();
+
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            let result_sender = result_sender.clone();
+            let job_receiver = &job_receiver;
+            let compile = &compile;
+            scope.spawn(move || loop {
+                let job = job_receiver.lock().unwrap().recv();
+                match job {
+                    Ok(module_name) => {
+                        let start = Instant::now();
+                        // a panic would stop this worker without the coordinator noticing, so
+                        // it's turned into an outcome that the coordinator waits for
+                        let mut outcome =
+                            panic::catch_unwind(AssertUnwindSafe(|| compile(&module_name)))
+                                .unwrap_or_else(|payload| {
+                                    CompileOutcome::panicked(
+                                        &module_name,
+                                        &get_panic_message(payload.as_ref()),
+                                    )
+                                });
+                        outcome.duration = start.elapsed();
+                        if result_sender.send(outcome).is_err() {
+                            break;
+                        }
+                    }
+                    // the coordinator hung up, there is no more work
+                    Err(_) => break,
+                }
+            });
+        }
+        drop(result_sender);
+
+        let mut in_flight = 0;
+        let mut last_event = Instant::now();
+
+        loop {
+            // hand out work to all idle workers
+            while !has_error && !cancel::is_cancelled(cancel_token) && in_flight < workers {
+                let Some(ReadyModule { module_name, .. }) = graph.ready.pop() else {
+                    break;
+                };
+                if graph.dirty_modules.contains(&module_name) {
+                    job_sender.send(module_name).unwrap();
+                    in_flight += 1;
+                    stats.jobs += 1;
+                } else {
+                    // we are sure we don't have to compile this, so we can mark it as
+                    // compiled and clean, without bothering a worker
+                    stats.skipped += 1;
+                    let outcome = CompileOutcome::skipped(&module_name);
+                    graph.finish(outcome, &mut on_finished);
+                }
+            }
+
+            // nothing is running and nothing can be started anymore: either everything is
+            // finished, we stopped because of an error or a cancel, or the rest is stuck in a cycle
+            if in_flight == 0 {
+                break;
+            }
+
+            let idle_workers = workers - in_flight;
+            stats.max_idle_workers = stats.max_idle_workers.max(idle_workers);
+            let outcome = result_receiver
+                .recv()
+                .expect("Compile worker stopped unexpectedly");
+            let now = Instant::now();
+            stats.idle_worker_time += (now - last_event) * idle_workers as u32;
+            last_event = now;
+            in_flight -= 1;
+
+            has_error = has_error || outcome.has_error();
+            graph.finish(outcome, &mut on_finished);
+        }
+
+        // closing the job channel stops the workers
+        drop(job_sender);
+    });
+
+    stats.wall_time = start.elapsed();
+    info!(
+        "Compiled {} modules ({} skipped) on {} workers in {:.2}s. Idle worker time: {:.2}s, at most {} idle workers, utilisation {:.0}%",
+        stats.jobs,
+        stats.skipped,
+        stats.workers,
+        stats.wall_time.as_secs_f64(),
+        stats.idle_worker_time.as_secs_f64(),
+        stats.max_idle_workers,
+        stats.utilisation() * 100.0
+    );
+
+    let unfinished_modules = compile_universe
+        .difference(&graph.finished)
+        .cloned()
+        .collect::<AHashSet<String>>();
+
+    SchedulerResult {
+        outcomes: graph.outcomes,
+        dirty_modules: graph.dirty_modules,
+        unfinished_modules,
+        stats,
+    }
+}
+
+fn get_panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+/// The part of the dependency graph that is relevant for the compile universe, together with the
+/// progress of the compile.
+struct Graph<'a> {
+    modules: &'a AHashMap<String, Module>,
+    compile_universe: &'a AHashSet<String>,
+    /// the amount of dependencies in the compile universe that still need to be finished
+    pending_deps: AHashMap<&'a str, usize>,
+    dirty_modules: AHashSet<String>,
+    /// the estimated cost of the longest chain of compile jobs that starts at a module
+    priorities: AHashMap<&'a str, u64>,
+    ready: BinaryHeap<ReadyModule>,
+    finished: AHashSet<String>,
+    outcomes: Vec<CompileOutcome>,
+}
+
+impl<'a> Graph<'a> {
+    fn new(
+        modules: &'a AHashMap<String, Module>,
+        compile_universe: &'a AHashSet<String>,
+        costs: &AHashMap<String, Duration>,
+    ) -> Self {
+        let pending_deps: AHashMap<&str, usize> = compile_universe
+            .iter()
+            .map(|module_name| {
+                let module = modules.get(module_name).unwrap();
+                (
+                    module_name.as_str(),
+                    module.deps.intersection(compile_universe).count(),
+                )
+            })
+            .collect();
+
+        let dirty_modules = compile_universe
+            .iter()
+            .filter(|module_name| modules.get(*module_name).unwrap().compile_dirty)
+            .cloned()
+            .collect::<AHashSet<String>>();
+
+        let priorities = get_priorities(modules, compile_universe, costs);
+
+        let ready = pending_deps
+            .iter()
+            .filter(|(_, pending)| **pending == 0)
+            .map(|(module_name, _)| ReadyModule {
+                priority: priorities[module_name],
+                module_name: module_name.to_string(),
+            })
+            .collect::<BinaryHeap<ReadyModule>>();
+
+        Self {
+            modules,
+            compile_universe,
+            pending_deps,
+            dirty_modules,
+            priorities,
+            ready,
+            finished: AHashSet::new(),
+            outcomes: Vec::with_capacity(compile_universe.len()),
+        }
+    }
+
+    /// Bookkeeping for a finished module: unblock the reverse dependencies whose last dependency
+    /// this was, and mark them as dirty if the interface of this module changed.
+    fn finish<P: FnMut(&CompileOutcome)>(&mut self, outcome: CompileOutcome, on_finished: &mut P) {
+        let module = self.modules.get(&outcome.module_name).unwrap();
+        for dep in module.reverse_deps.intersection(self.compile_universe) {
+            if !outcome.is_clean {
+                self.dirty_modules.insert(dep.to_string());
+            }
+            if let Some(pending) = self.pending_deps.get_mut(dep.as_str()) {
+                *pending -= 1;
+                if *pending == 0 {
+                    self.ready.push(ReadyModule {
+                        priority: self.priorities[dep.as_str()],
+                        module_name: dep.to_string(),
+                    });
+                }
+            }
+        }
+        on_finished(&outcome);
+        self.finished.insert(outcome.module_name.to_owned());
+        self.outcomes.push(outcome);
+    }
+}
+
+/// A module of which all dependencies are finished. The heap of ready modules pops the module with
+/// the highest priority first, and the alphabetically first one when the priorities are equal, so
+/// the order is deterministic.
+#[derive(Debug, PartialEq, Eq)]
+struct ReadyModule {
+    priority: u64,
+    module_name: String,
+}
+
+impl Ord for ReadyModule {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.module_name.cmp(&self.module_name))
+    }
+}
+
+impl PartialOrd for ReadyModule {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The priority of a module is the cost of the most expensive chain of reverse dependencies in the
+/// compile universe that starts at that module (including the module itself). The cost of a module
+/// is its compile time in the previous build in microseconds. Modules we don't have a timing for
+/// are assumed to take the average time, so without any timings the priority is the length of the
+/// longest chain of modules that is waiting for this module.
+fn get_priorities<'a>(
+    modules: &'a AHashMap<String, Module>,
+    compile_universe: &'a AHashSet<String>,
+    costs: &AHashMap<String, Duration>,
+) -> AHashMap<&'a str, u64> {
+    let known_costs = compile_universe
+        .iter()
+        .filter_map(|module_name| costs.get(module_name))
+        .map(|cost| cost.as_micros() as u64)
+        .collect::<Vec<u64>>();
+    let default_cost = if known_costs.is_empty() {
+        1
+    } else {
+        (known_costs.iter().sum::<u64>() / known_costs.len() as u64).max(1)
+    };
+
+    let cost = |module_name: &str| {
+        costs
+            .get(module_name)
+            .map(|cost| (cost.as_micros() as u64).max(1))
+            .unwrap_or(default_cost)
+    };
+    let reverse_deps = |module_name: &str| {
+        modules
+            .get(module_name)
+            .unwrap()
+            .reverse_deps
+            .iter()
+            .filter(|dep| compile_universe.contains(*dep))
+            .map(|dep| dep.as_str())
+            .collect::<Vec<&'a str>>()
+    };
+
+    // the priority of a module is known when the priorities of all its reverse dependencies are,
+    // so the modules are visited in reverse topological order, starting at the modules nothing in
+    // the compile universe depends on. This is iterative, as the chains can be very long.
+    let mut pending_reverse_deps: AHashMap<&str, usize> = AHashMap::new();
+    let mut waiting_modules: AHashMap<&str, Vec<&str>> = AHashMap::new();
+    for module_name in compile_universe.iter() {
+        let reverse_deps = reverse_deps(module_name);
+        pending_reverse_deps.insert(module_name.as_str(), reverse_deps.len());
+        for dep in reverse_deps {
+            waiting_modules
+                .entry(dep)
+                .or_default()
+                .push(module_name.as_str());
+        }
+    }
+    let mut stack = pending_reverse_deps
+        .iter()
+        .filter(|(_, pending)| **pending == 0)
+        .map(|(module_name, _)| *module_name)
+        .collect::<Vec<&str>>();
+
+    let mut priorities: AHashMap<&str, u64> = AHashMap::new();
+    while let Some(module_name) = stack.pop() {
+        let most_expensive_reverse_dep = reverse_deps(module_name)
+            .iter()
+            .map(|dep| priorities[dep])
+            .max()
+            .unwrap_or(0);
+        priorities.insert(module_name, cost(module_name) + most_expensive_reverse_dep);
+        for waiting_module in waiting_modules.get(module_name).into_iter().flatten() {
+            let pending = pending_reverse_deps.get_mut(waiting_module).unwrap();
+            *pending -= 1;
+            if *pending == 0 {
+                stack.push(waiting_module);
+            }
+        }
+    }
+
+    // the modules in (or waiting for) a dependency cycle are never compiled anyway, they only
+    // count the reverse dependencies we know about
+    for module_name in compile_universe.iter() {
+        if !priorities.contains_key(module_name.as_str()) {
+            let most_expensive_reverse_dep = reverse_deps(module_name)
+                .iter()
+                .filter_map(|dep| priorities.get(dep))
+                .max()
+                .copied()
+                .unwrap_or(0);
+            priorities.insert(module_name, cost(module_name) + most_expensive_reverse_dep);
+        }
+    }
+    priorities
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn module(deps: &[&str], reverse_deps: &[&str], compile_dirty: bool) -> Module {
+        Module {
+            source_type: SourceType::MlMap(MlMap { dirty: false }),
+            deps: deps.iter().map(|dep| dep.to_string()).collect(),
+            reverse_deps: reverse_deps.iter().map(|dep| dep.to_string()).collect(),
+            package_name: "package".to_string(),
+            compile_dirty,
+            deps_dirty: false,
+            is_type_dev: false,
+        }
+    }
+
+    fn outcome(module_name: &str, is_clean: bool, error: bool) -> CompileOutcome {
+        CompileOutcome {
+            module_name: module_name.to_string(),
+            result: if error {
+                Err("error".to_string())
+            } else {
+                Ok(None)
+            },
+            interface_result: None,
+            is_clean,
+            is_compiled: true,
+            is_restored: false,
+            duration: Duration::ZERO,
+        }
+    }
+
+    // A <- B <- D
+    // A <- C <- D
+    fn diamond(dirty: &[&str]) -> AHashMap<String, Module> {
+        let mut modules = AHashMap::new();
+        modules.insert(
+            "A".to_string(),
+            module(&[], &["B", "C"], dirty.contains(&"A")),
+        );
+        modules.insert(
+            "B".to_string(),
+            module(&["A"], &["D"], dirty.contains(&"B")),
+        );
+        modules.insert(
+            "C".to_string(),
+            module(&["A"], &["D"], dirty.contains(&"C")),
+        );
+        modules.insert(
+            "D".to_string(),
+            module(&["B", "C"], &[], dirty.contains(&"D")),
+        );
+        modules
+    }
+
+    fn universe(modules: &AHashMap<String, Module>) -> AHashSet<String> {
+        modules.keys().cloned().collect()
+    }
+
+    #[test]
+    fn test_compiles_dependencies_first() {
+        let modules = diamond(&["A"]);
+        let mut order = vec![];
+        let result = run(
+            &modules,
+            &universe(&modules),
+            &AHashMap::new(),
+            2,
+            None,
+            |module_name| outcome(module_name, false, false),
+            |outcome| order.push(outcome.module_name.to_owned()),
+        );
+
+        assert_eq!(order.len(), 4);
+        assert_eq!(order[0], "A");
+        assert_eq!(order[3], "D");
+        assert_eq!(result.stats.jobs, 4);
+        assert!(result.unfinished_modules.is_empty());
+    }
+
+    #[test]
+    fn test_clean_cmi_stops_propagation() {
+        let modules = diamond(&["A"]);
+        let result = run(
+            &modules,
+            &universe(&modules),
+            &AHashMap::new(),
+            1,
+            None,
+            |module_name| outcome(module_name, true, false),
+            |_| (),
+        );
+
+        assert_eq!(result.stats.jobs, 1);
+        assert_eq!(result.stats.skipped, 3);
+        assert!(result.outcomes.iter().all(|outcome| outcome.is_clean));
+    }
+
+    #[test]
+    fn test_error_stops_scheduling() {
+        let modules = diamond(&["A"]);
+        let result = run(
+            &modules,
+            &universe(&modules),
+            &AHashMap::new(),
+            4,
+            None,
+            |module_name| outcome(module_name, false, module_name == "A"),
+            |_| (),
+        );
+
+        assert_eq!(result.stats.jobs, 1);
+        assert_eq!(result.unfinished_modules.len(), 3);
+    }
+
+    #[test]
+    fn test_panic_is_a_compile_error() {
+        let modules = diamond(&["A"]);
+        let result = run(
+            &modules,
+            &universe(&modules),
+            &AHashMap::new(),
+            4,
+            None,
+            |module_name| {
+                if module_name == "A" {
+                    panic!("Not a source file");
+                }
+                outcome(module_name, false, false)
+            },
+            |_| (),
+        );
+
+        assert_eq!(result.outcomes.len(), 1);
+        assert_eq!(
+            result.outcomes[0].result,
+            Err("Compiling A failed unexpectedly: Not a source file".to_string())
+        );
+        assert_eq!(result.unfinished_modules.len(), 3);
+    }
+
+    #[test]
+    fn test_cancel_stops_scheduling() {
+        let modules = diamond(&["A"]);
+        let cancel_token = CancelToken::new();
+        let result = run(
+            &modules,
+            &universe(&modules),
+            &AHashMap::new(),
+            4,
+            Some(&cancel_token),
+            |module_name| {
+                cancel_token.cancel();
+                outcome(module_name, false, false)
+            },
+            |_| (),
+        );
+
+        assert_eq!(result.stats.jobs, 1);
+        assert_eq!(result.unfinished_modules.len(), 3);
+    }
+
+    #[test]
+    fn test_priorities_of_a_long_chain() {
+        // M0 <- M1 <- ... <- M99999, deeper than the stack allows for recursion
+        let length = 100_000;
+        let name = |i: usize| format!("M{}", i);
+        let modules = (0..length)
+            .map(|i| {
+                let deps = if i > 0 { vec![name(i - 1)] } else { vec![] };
+                let reverse_deps = if i + 1 < length {
+                    vec![name(i + 1)]
+                } else {
+                    vec![]
+                };
+                (
+                    name(i),
+                    module(
+                        &deps.iter().map(String::as_str).collect::<Vec<&str>>(),
+                        &reverse_deps
+                            .iter()
+                            .map(String::as_str)
+                            .collect::<Vec<&str>>(),
+                        true,
+                    ),
+                )
+            })
+            .collect::<AHashMap<String, Module>>();
+        let compile_universe = universe(&modules);
+
+        let priorities = get_priorities(&modules, &compile_universe, &AHashMap::new());
+        assert_eq!(priorities["M0"], length as u64);
+        assert_eq!(priorities["M99999"], 1);
+    }
+
+    #[test]
+    fn test_critical_path_goes_first() {
+        // A <- B <- C, D and E are standalone
+        let mut modules = AHashMap::new();
+        modules.insert("A".to_string(), module(&[], &["B"], true));
+        modules.insert("B".to_string(), module(&["A"], &["C"], true));
+        modules.insert("C".to_string(), module(&["B"], &[], true));
+        modules.insert("D".to_string(), module(&[], &[], true));
+        modules.insert("E".to_string(), module(&[], &[], true));
+
+        let mut order = vec![];
+        run(
+            &modules,
+            &universe(&modules),
+            &AHashMap::new(),
+            1,
+            None,
+            |module_name| outcome(module_name, false, false),
+            |outcome| order.push(outcome.module_name.to_owned()),
+        );
+        assert_eq!(order, vec!["A", "B", "C", "D", "E"]);
+
+        // when D was expensive last time, it has to go first
+        let costs = AHashMap::from([
+            ("A".to_string(), Duration::from_millis(1)),
+            ("B".to_string(), Duration::from_millis(1)),
+            ("C".to_string(), Duration::from_millis(1)),
+            ("D".to_string(), Duration::from_secs(10)),
+            ("E".to_string(), Duration::from_millis(1)),
+        ]);
+        let mut order = vec![];
+        run(
+            &modules,
+            &universe(&modules),
+            &costs,
+            1,
+            None,
+            |module_name| outcome(module_name, false, false),
+            |outcome| order.push(outcome.module_name.to_owned()),
+        );
+        assert_eq!(order, vec!["D", "A", "B", "C", "E"]);
+    }
+}
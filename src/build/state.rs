@@ -0,0 +1,228 @@
+//! The build state of the previous build, persisted to disk.
+//!
+//! Every build writes the modules it knows about, with the content hashes of the sources they
+//! were parsed from, their dependencies, the digest of the cmi they compiled to, and fingerprints
+//! of the compiler version and arguments that were used. The next build (or a restarted watcher)
+//! reads it back to decide which modules are dirty. Because this is based on the contents of the
+//! files, and not on their timestamps, a `git checkout` or a restored CI cache doesn't trigger a
+//! full rebuild. Upgrading the compiler or changing the flags in a bsconfig does.
+
+use crate::build;
+use crate::build_types::*;
+use crate::helpers;
+use ahash::{AHashMap, AHashSet};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Bump this when the format of the file changes, files with another version are ignored.
+const VERSION: u32 = 3;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PersistedSourceFile {
+    pub path: String,
+    /// the hash of the contents the AST was generated from, `None` when it failed to parse
+    pub hash: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PersistedModule {
+    pub package_name: String,
+    pub implementation: PersistedSourceFile,
+    pub interface: Option<PersistedSourceFile>,
+    pub deps: AHashSet<String>,
+    /// the digest of the cmi after the last successful compile, `None` when the module still
+    /// needs to be compiled
+    pub cmi_digest: Option<String>,
+    /// fingerprint of the compiler version and the arguments the module was compiled with
+    pub compile_inputs: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PersistedPackage {
+    /// fingerprint of the arguments the sources were parsed with, the ASTs only need to be
+    /// generated again when this changes
+    pub parse_inputs: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PersistedBuildState {
+    pub version: u32,
+    pub rescript_version: String,
+    pub packages: AHashMap<String, PersistedPackage>,
+    pub modules: AHashMap<String, PersistedModule>,
+}
+
+fn get_state_path(project_root: &str) -> PathBuf {
+    Path::new(project_root)
+        .join("lib")
+        .join("bs")
+        .join(".build_state.json")
+}
+
+pub fn hash_to_string(hash: &blake3::Hash) -> String {
+    hash.to_hex().to_string()
+}
+
+/// Reads the state of the previous build. Returns `None` when there is no previous build, or when
+/// it was written by a version of rewatch with a different format.
+pub fn read(project_root: &str) -> Option<PersistedBuildState> {
+    let path = get_state_path(project_root);
+    let state = fs::read_to_string(&path)
+        .ok()
+        .and_then(|contents| serde_json::from_str::<PersistedBuildState>(&contents).ok());
+    match state {
+        Some(state) if state.version == VERSION => Some(state),
+        Some(_) => {
+            debug!("Ignoring build state with another version: {:?}", path);
+            None
+        }
+        None => None,
+    }
+}
+
+/// A fingerprint of the arguments every package is parsed with in this build.
+pub fn get_parse_fingerprints(
+    build_state: &BuildState,
+    rescript_version: &str,
+) -> AHashMap<String, String> {
+    let root_package = build_state
+        .get_package(&build_state.root_config_name)
+        .expect("Root package not found");
+    build_state
+        .packages
+        .iter()
+        .map(|(package_name, package)| {
+            let parse_args = build::get_parse_args(rescript_version, package, root_package);
+            (
+                package_name.to_owned(),
+                hash_to_string(&blake3::hash(parse_args.join("\0").as_bytes())),
+            )
+        })
+        .collect()
+}
+
+/// A fingerprint of the compiler version and the arguments a module is compiled with in this
+/// build. When it changes, the module needs to be compiled again, even when its sources didn't.
+pub fn get_compile_fingerprint(
+    build_state: &BuildState,
+    module: &Module,
+    rescript_version: &str,
+) -> Option<String> {
+    if let SourceType::MlMap(_) = module.source_type {
+        return None;
+    }
+    let package = build_state.get_package(&module.package_name)?;
+    let root_package = build_state.get_package(&build_state.root_config_name)?;
+    let compiler_args = |is_interface| {
+        build::get_compiler_args(
+            package,
+            root_package,
+            module,
+            rescript_version,
+            is_interface,
+        )
+    };
+    let interface_args = match build::get_interface(module) {
+        Some(_) => compiler_args(true),
+        None => vec![],
+    };
+    let fingerprint = [
+        vec![rescript_version.to_string()],
+        compiler_args(false),
+        interface_args,
+    ]
+    .concat()
+    .join("\0");
+    Some(hash_to_string(&blake3::hash(fingerprint.as_bytes())))
+}
+
+fn persist_source_file(
+    path: &str,
+    hash: &Option<blake3::Hash>,
+    parse_state: &ParseState,
+    dirty: bool,
+) -> PersistedSourceFile {
+    PersistedSourceFile {
+        path: path.to_string(),
+        hash: match parse_state {
+            ParseState::ParseError => None,
+            _ if dirty => None,
+            _ => hash.as_ref().map(hash_to_string),
+        },
+    }
+}
+
+/// Writes the state of the build that just finished to disk.
+pub fn write(build_state: &BuildState, rescript_version: &str) {
+    let packages = get_parse_fingerprints(build_state, rescript_version)
+        .into_iter()
+        .map(|(package_name, parse_inputs)| (package_name, PersistedPackage { parse_inputs }))
+        .collect();
+
+    let modules = build_state
+        .modules
+        .iter()
+        .filter_map(|(module_name, module)| match &module.source_type {
+            SourceType::MlMap(_) => None,
+            SourceType::SourceFile(source_file) => {
+                let package = build_state.get_package(&module.package_name)?;
+                let compile_inputs =
+                    get_compile_fingerprint(build_state, module, rescript_version)?;
+                let cmi_digest = if module.compile_dirty {
+                    None
+                } else {
+                    helpers::compute_file_hash(&helpers::get_compiler_asset(
+                        &source_file.implementation.path,
+                        package,
+                        &package.namespace,
+                        "cmi",
+                    ))
+                    .as_ref()
+                    .map(hash_to_string)
+                };
+                Some((
+                    module_name.to_owned(),
+                    PersistedModule {
+                        package_name: module.package_name.to_owned(),
+                        implementation: persist_source_file(
+                            &source_file.implementation.path,
+                            &source_file.implementation.hash,
+                            &source_file.implementation.parse_state,
+                            source_file.implementation.dirty,
+                        ),
+                        interface: source_file.interface.as_ref().map(|interface| {
+                            persist_source_file(
+                                &interface.path,
+                                &interface.hash,
+                                &interface.parse_state,
+                                interface.dirty,
+                            )
+                        }),
+                        deps: module.deps.to_owned(),
+                        cmi_digest,
+                        compile_inputs,
+                    },
+                ))
+            }
+        })
+        .collect();
+
+    let state = PersistedBuildState {
+        version: VERSION,
+        rescript_version: rescript_version.to_string(),
+        packages,
+        modules,
+    };
+
+    let path = get_state_path(&build_state.project_root);
+    let result = path
+        .parent()
+        .map(fs::create_dir_all)
+        .unwrap_or(Ok(()))
+        .and_then(|_| fs::write(&path, serde_json::to_string(&state).unwrap()));
+    if let Err(e) = result {
+        debug!("Could not write build state to {:?}: {}", path, e);
+    }
+}
@@ -0,0 +1,78 @@
+//! Compile timings of the previous build. They are used to give modules on the critical path of the
+//! build a head start in the compile scheduler.
+
+use crate::build::scheduler::CompileOutcome;
+use ahash::AHashMap;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct TimingsFile {
+    version: u32,
+    /// compile time of every module in microseconds
+    modules: AHashMap<String, u64>,
+}
+
+fn get_timings_path(project_root: &str) -> PathBuf {
+    Path::new(project_root)
+        .join("lib")
+        .join("bs")
+        .join(".compile_timings.json")
+}
+
+/// Reads the compile timings of the previous build. A missing or unreadable file just means
+/// there are no timings (yet).
+pub fn read(project_root: &str) -> AHashMap<String, Duration> {
+    fs::read_to_string(get_timings_path(project_root))
+        .ok()
+        .and_then(|contents| serde_json::from_str::<TimingsFile>(&contents).ok())
+        .filter(|timings| timings.version == VERSION)
+        .map(|timings| {
+            timings
+                .modules
+                .into_iter()
+                .map(|(module_name, micros)| (module_name, Duration::from_micros(micros)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Updates the timings of the previous build with the modules that were compiled in this build,
+/// and writes them back. Modules that do not exist anymore are dropped.
+pub fn write(
+    project_root: &str,
+    mut timings: AHashMap<String, Duration>,
+    outcomes: &[CompileOutcome],
+    module_exists: impl Fn(&str) -> bool,
+) {
+    outcomes
+        .iter()
+        .filter(|outcome| outcome.is_compiled)
+        .for_each(|outcome| {
+            timings.insert(outcome.module_name.to_owned(), outcome.duration);
+        });
+    timings.retain(|module_name, _| module_exists(module_name));
+
+    let timings_file = TimingsFile {
+        version: VERSION,
+        modules: timings
+            .into_iter()
+            .map(|(module_name, duration)| (module_name, duration.as_micros() as u64))
+            .collect(),
+    };
+
+    let path = get_timings_path(project_root);
+    let result = path
+        .parent()
+        .map(fs::create_dir_all)
+        .unwrap_or(Ok(()))
+        .and_then(|_| fs::write(&path, serde_json::to_string(&timings_file).unwrap()));
+    if let Err(e) = result {
+        debug!("Could not write compile timings to {:?}: {}", path, e);
+    }
+}
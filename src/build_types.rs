@@ -1,8 +1,10 @@
+use crate::build::diagnostics::Diagnostic;
 use crate::package_tree::Package;
 use ahash::{AHashMap, AHashSet};
-use std::time::SystemTime;
+use serde::Serialize;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ParseState {
     Pending,
     ParseError,
@@ -10,7 +12,8 @@ pub enum ParseState {
     Success,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum CompileState {
     Pending,
     Error,
@@ -22,8 +25,13 @@ pub struct Interface {
     pub path: String,
     pub parse_state: ParseState,
     pub compile_state: CompileState,
-    pub last_modified: SystemTime,
+    /// blake3 hash of the contents of the source file, computed before parsing
+    pub hash: Option<blake3::Hash>,
     pub dirty: bool,
+    /// warnings and errors of the last time the file was parsed
+    pub parse_diagnostics: Vec<Diagnostic>,
+    /// warnings and errors of the last time the file was compiled
+    pub compile_diagnostics: Vec<Diagnostic>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -31,8 +39,13 @@ pub struct Implementation {
     pub path: String,
     pub parse_state: ParseState,
     pub compile_state: CompileState,
-    pub last_modified: SystemTime,
+    /// blake3 hash of the contents of the source file, computed before parsing
+    pub hash: Option<blake3::Hash>,
     pub dirty: bool,
+    /// warnings and errors of the last time the file was parsed
+    pub parse_diagnostics: Vec<Diagnostic>,
+    /// warnings and errors of the last time the file was compiled
+    pub compile_diagnostics: Vec<Diagnostic>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -47,6 +60,7 @@ pub struct MlMap {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::large_enum_variant)]
 pub enum SourceType {
     SourceFile(SourceFile),
     MlMap(MlMap),
@@ -59,6 +73,11 @@ pub struct Module {
     pub reverse_deps: AHashSet<String>,
     pub package_name: String,
     pub compile_dirty: bool,
+    /// the dependencies are read from the AST again when it was generated again
+    pub deps_dirty: bool,
+    /// whether the module is in a source with the type 'dev', these can only be used by other dev
+    /// modules of the same package
+    pub is_type_dev: bool,
 }
 
 #[derive(Debug)]
@@ -86,9 +105,9 @@ impl BuildState {
         Self {
             module_names: AHashSet::new(),
             modules: AHashMap::new(),
-            packages: packages,
-            project_root: project_root,
-            root_config_name: root_config_name,
+            packages,
+            project_root,
+            root_config_name,
         }
     }
     pub fn insert_module(&mut self, module_name: &str, module: Module) {
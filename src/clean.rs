@@ -1,4 +1,5 @@
 use crate::build;
+use crate::build::state;
 use crate::build_types::*;
 use crate::helpers;
 use crate::helpers::get_mlmap_path;
@@ -6,7 +7,6 @@ use crate::package_tree;
 use ahash::{AHashMap, AHashSet};
 use rayon::prelude::*;
 use std::fs;
-use std::time::SystemTime;
 
 pub fn get_res_path_from_ast(ast_file: &str) -> Option<String> {
     if let Ok(lines) = helpers::read_lines(ast_file.to_string()) {
@@ -21,79 +21,323 @@ pub fn get_res_path_from_ast(ast_file: &str) -> Option<String> {
             }
         }
     }
-    return None;
+    None
 }
 
-fn remove_asts(source_file: &str, package_name: &str, root_path: &str) {
+fn remove_asts(source_file: &str, package: &package_tree::Package) {
     let _ = std::fs::remove_file(helpers::get_compiler_asset(
         source_file,
-        package_name,
+        package,
         &package_tree::Namespace::NoNamespace,
-        root_path,
         "ast",
     ));
     let _ = std::fs::remove_file(helpers::get_compiler_asset(
         source_file,
-        package_name,
+        package,
         &package_tree::Namespace::NoNamespace,
-        root_path,
         "iast",
     ));
 }
 
-fn remove_mjs_file(source_file: &str) {
-    let _ = std::fs::remove_file(helpers::change_extension(source_file, "mjs"));
+fn remove_js_files(
+    source_file: &str,
+    package: &package_tree::Package,
+    root_package: &package_tree::Package,
+) {
+    for js_file in helpers::get_js_paths(source_file, package, root_package) {
+        let _ = std::fs::remove_file(js_file);
+    }
 }
 
 fn remove_compile_assets(
     source_file: &str,
-    package_name: &str,
+    package: &package_tree::Package,
     namespace: &package_tree::Namespace,
-    root_path: &str,
 ) {
     // optimization
     // only issue cmti if htere is an interfacce file
     for extension in &["cmj", "cmi", "cmt", "cmti"] {
         let _ = std::fs::remove_file(helpers::get_compiler_asset(
             source_file,
-            package_name,
+            package,
             namespace,
-            root_path,
             extension,
         ));
         let _ = std::fs::remove_file(helpers::get_bs_compiler_asset(
             source_file,
-            package_name,
+            package,
             namespace,
-            root_path,
             extension,
         ));
     }
 }
 
-pub fn clean_mjs_files(all_modules: &AHashMap<String, Module>) {
+/// Removes the ASTs, the compiler assets and the JavaScript of a module whose source files are
+/// gone.
+pub fn remove_module_files(build_state: &BuildState, module: &Module) {
+    let source_file = match &module.source_type {
+        SourceType::SourceFile(source_file) => source_file,
+        SourceType::MlMap(_) => return,
+    };
+    let package = match build_state.packages.get(&module.package_name) {
+        Some(package) => package,
+        None => return,
+    };
+    let root_package = build_state
+        .get_package(&build_state.root_config_name)
+        .expect("Root package not found");
+    let paths = std::iter::once(&source_file.implementation.path)
+        .chain(
+            source_file
+                .interface
+                .as_ref()
+                .map(|interface| &interface.path),
+        )
+        .filter(|path| !path.is_empty());
+    for path in paths {
+        remove_asts(path, package);
+        remove_compile_assets(
+            &helpers::canonicalize_parent_string_path(path).unwrap_or(path.to_string()),
+            package,
+            &package.namespace,
+        );
+    }
+    if !source_file.implementation.path.is_empty() {
+        remove_js_files(&source_file.implementation.path, package, root_package);
+    }
+}
+
+pub fn clean_js_files(build_state: &BuildState) {
+    let root_package = build_state
+        .get_package(&build_state.root_config_name)
+        .expect("Root package not found");
     // get all rescript file locations
-    let rescript_file_locations = all_modules
+    let rescript_file_locations = build_state
+        .modules
         .values()
         .filter_map(|module| match &module.source_type {
-            SourceType::SourceFile(source_file) => {
-                Some(source_file.implementation.path.to_string())
-            }
+            SourceType::SourceFile(source_file) => Some((
+                source_file.implementation.path.to_string(),
+                module.package_name.to_string(),
+            )),
             _ => None,
         })
-        .collect::<AHashSet<String>>();
+        .collect::<AHashSet<(String, String)>>();
 
     rescript_file_locations
         .par_iter()
-        .for_each(|rescript_file_location| remove_mjs_file(&rescript_file_location));
+        .for_each(|(rescript_file_location, package_name)| {
+            if let Some(package) = build_state.get_package(package_name) {
+                remove_js_files(rescript_file_location, package, root_package)
+            }
+        });
+}
+
+/// Hashes the contents of all the source files, so we can compare them to the sources of the
+/// previous build.
+fn hash_source_files(build_state: &mut BuildState) {
+    build_state
+        .modules
+        .par_iter_mut()
+        .for_each(|(_module_name, module)| {
+            if let SourceType::SourceFile(ref mut source_file) = module.source_type {
+                source_file.implementation.hash =
+                    helpers::compute_file_hash(&source_file.implementation.path);
+                if let Some(interface) = source_file.interface.as_mut() {
+                    interface.hash = helpers::compute_file_hash(&interface.path);
+                }
+            }
+        });
+}
+
+/// Figures out what changed since the previous build: marks the modules that need to be parsed
+/// or compiled again as dirty, and removes the compiler assets of modules that don't exist
+/// anymore. Returns the amount of removed modules, the total amount of modules of the previous
+/// build, and the names of the deleted modules.
+pub fn cleanup_previous_build(
+    build_state: &mut BuildState,
+    rescript_version: &str,
+) -> (usize, usize, AHashSet<String>) {
+    hash_source_files(build_state);
+    match state::read(&build_state.project_root) {
+        Some(previous_state) => {
+            cleanup_with_previous_state(build_state, previous_state, rescript_version)
+        }
+        // there is no (compatible) state of a previous build, so we fall back to looking at the
+        // compiler assets on disk
+        None => cleanup_with_compiler_assets(build_state),
+    }
+}
+
+fn source_file_unchanged(
+    previous: &Option<state::PersistedSourceFile>,
+    path: &str,
+    hash: &Option<blake3::Hash>,
+) -> bool {
+    match (previous, hash) {
+        (Some(previous), Some(hash)) => {
+            previous.path == path && previous.hash.as_ref() == Some(&state::hash_to_string(hash))
+        }
+        _ => false,
+    }
+}
+
+fn cleanup_with_previous_state(
+    build_state: &mut BuildState,
+    previous_state: state::PersistedBuildState,
+    rescript_version: &str,
+) -> (usize, usize, AHashSet<String>) {
+    let same_compiler = previous_state.rescript_version == rescript_version;
+    let compile_fingerprints = build_state
+        .modules
+        .iter()
+        .filter_map(|(module_name, module)| {
+            state::get_compile_fingerprint(build_state, module, rescript_version)
+                .map(|fingerprint| (module_name.to_owned(), fingerprint))
+        })
+        .collect::<AHashMap<String, String>>();
+    let parse_fingerprints = state::get_parse_fingerprints(build_state, rescript_version);
+
+    // the modules of the previous build that don't exist anymore, or that moved to another file
+    let mut stale_modules = vec![];
+
+    for (module_name, module) in build_state.modules.iter_mut() {
+        let source_file = match module.source_type {
+            SourceType::SourceFile(ref mut source_file) => source_file,
+            SourceType::MlMap(_) => continue,
+        };
+        let previous = match previous_state.modules.get(module_name) {
+            Some(previous) => previous,
+            None => continue,
+        };
+        if previous.implementation.path != source_file.implementation.path {
+            stale_modules.push((module_name.to_owned(), previous.to_owned()));
+        }
+
+        let previous_package = if previous.package_name == module.package_name {
+            previous_state.packages.get(&module.package_name)
+        } else {
+            None
+        };
+        // the parse inputs include the compiler version, so this also catches compiler upgrades
+        let same_parse_inputs = previous_package
+            .map(|package| {
+                Some(&package.parse_inputs) == parse_fingerprints.get(&module.package_name)
+            })
+            .unwrap_or(false);
+        let same_compile_inputs =
+            compile_fingerprints.get(module_name) == Some(&previous.compile_inputs);
+        if !same_parse_inputs {
+            continue;
+        }
+
+        let package = build_state
+            .packages
+            .get(&module.package_name)
+            .expect("Package not found");
+        let implementation = &mut source_file.implementation;
+        let ast_exists =
+            std::path::Path::new(&helpers::get_ast_path(&implementation.path, package)).exists();
+        if ast_exists
+            && source_file_unchanged(
+                &Some(previous.implementation.to_owned()),
+                &implementation.path,
+                &implementation.hash,
+            )
+        {
+            implementation.dirty = false;
+        }
+
+        let interface_unchanged = match source_file.interface.as_mut() {
+            Some(interface) => {
+                let iast_exists =
+                    std::path::Path::new(&helpers::get_iast_path(&interface.path, package))
+                        .exists();
+                if iast_exists
+                    && source_file_unchanged(&previous.interface, &interface.path, &interface.hash)
+                {
+                    interface.dirty = false;
+                }
+                !interface.dirty
+            }
+            None => previous.interface.is_none(),
+        };
+
+        // when the sources didn't change, the module only needs to be compiled again when the
+        // compiler assets are not the ones we produced in the previous build
+        if same_compiler
+            && same_compile_inputs
+            && !source_file.implementation.dirty
+            && interface_unchanged
+        {
+            let cmi_digest = helpers::compute_file_hash(&helpers::get_compiler_asset(
+                &source_file.implementation.path,
+                package,
+                &package.namespace,
+                "cmi",
+            ));
+            if cmi_digest.is_some()
+                && previous.cmi_digest == cmi_digest.as_ref().map(state::hash_to_string)
+            {
+                module.compile_dirty = false;
+            }
+        }
+    }
+
+    stale_modules.extend(
+        previous_state
+            .modules
+            .iter()
+            .filter(|(module_name, _)| !build_state.modules.contains_key(*module_name))
+            .map(|(module_name, module)| (module_name.to_owned(), module.to_owned())),
+    );
+
+    let root_package = build_state
+        .get_package(&build_state.root_config_name)
+        .expect("Root package not found");
+    stale_modules.par_iter().for_each(|(_module_name, module)| {
+        if let Some(package) = build_state.packages.get(&module.package_name) {
+            let paths = std::iter::once(&module.implementation)
+                .chain(module.interface.iter())
+                .map(|source_file| source_file.path.to_string())
+                .collect::<Vec<String>>();
+            for path in paths {
+                remove_asts(&path, package);
+                remove_compile_assets(
+                    &helpers::canonicalize_parent_string_path(&path).unwrap_or(path.to_string()),
+                    package,
+                    &package.namespace,
+                );
+            }
+            remove_js_files(&module.implementation.path, package, root_package);
+        }
+    });
+
+    let deleted_module_names = stale_modules
+        .iter()
+        .filter(|(module_name, _)| !build_state.modules.contains_key(module_name))
+        .map(|(module_name, _)| {
+            // if the module is a namespace, we need to mark the whole namespace as dirty when a module has been deleted
+            if let Some(namespace) = helpers::get_namespace_from_module_name(module_name) {
+                return namespace;
+            }
+            module_name.to_string()
+        })
+        .collect::<AHashSet<String>>();
+
+    (
+        stale_modules.len(),
+        previous_state.modules.len(),
+        deleted_module_names,
+    )
 }
 
-pub fn cleanup_previous_build(build_state: &mut BuildState) -> (usize, usize, AHashSet<String>) {
-    let mut ast_modules: AHashMap<
-        String,
-        (String, String, package_tree::Namespace, SystemTime, String),
-    > = AHashMap::new();
-    let mut cmi_modules: AHashMap<String, SystemTime> = AHashMap::new();
+/// Without the state of a previous build, we don't know which sources the existing compiler assets
+/// were generated from, so every module gets parsed and compiled again. The ASTs on disk are only
+/// used to find (and remove) the compiler assets of modules that don't exist anymore.
+fn cleanup_with_compiler_assets(build_state: &mut BuildState) -> (usize, usize, AHashSet<String>) {
+    let mut ast_modules: AHashMap<String, (String, String, package_tree::Namespace)> =
+        AHashMap::new();
     let mut ast_rescript_file_locations = AHashSet::new();
 
     let mut rescript_file_locations = build_state
@@ -121,62 +365,28 @@ pub fn cleanup_previous_build(build_state: &mut BuildState) -> (usize, usize, AH
 
     // scan all ast files in all packages
     for package in build_state.packages.values() {
-        let read_dir = fs::read_dir(std::path::Path::new(&helpers::get_build_path(
-            &build_state.project_root,
-            &package.name,
-        )))
-        .unwrap();
+        let read_dir = fs::read_dir(std::path::Path::new(&package.get_build_path())).unwrap();
 
-        for entry in read_dir {
-            match entry {
-                Ok(entry) => {
-                    let path = entry.path();
-                    let extension = path.extension().and_then(|e| e.to_str());
-                    match extension {
-                        Some(ext) => match ext {
-                            "iast" | "ast" => {
-                                let module_name = helpers::file_path_to_module_name(
-                                    path.to_str().unwrap(),
-                                    &package.namespace,
-                                );
-
-                                let ast_file_path = path.to_str().unwrap().to_owned();
-                                let res_file_path = get_res_path_from_ast(&ast_file_path);
-                                match res_file_path {
-                                    Some(res_file_path) => {
-                                        let _ = ast_modules.insert(
-                                            res_file_path.to_owned(),
-                                            (
-                                                module_name,
-                                                package.name.to_owned(),
-                                                package.namespace.to_owned(),
-                                                entry.metadata().unwrap().modified().unwrap(),
-                                                ast_file_path,
-                                            ),
-                                        );
-                                        let _ = ast_rescript_file_locations.insert(res_file_path);
-                                    }
-                                    None => (),
-                                }
-                            }
-                            "cmi" => {
-                                let module_name = helpers::file_path_to_module_name(
-                                    path.to_str().unwrap(),
-                                    &package.namespace,
-                                );
-                                cmi_modules.insert(
-                                    module_name,
-                                    entry.metadata().unwrap().modified().unwrap(),
-                                );
-                            }
-                            _ => {
-                                // println!("other extension: {:?}", other);
-                            }
-                        },
-                        None => (),
-                    }
+        for entry in read_dir.flatten() {
+            let path = entry.path();
+            let extension = path.extension().and_then(|e| e.to_str());
+            if let Some("iast" | "ast") = extension {
+                let module_name =
+                    helpers::file_path_to_module_name(path.to_str().unwrap(), &package.namespace);
+
+                let ast_file_path = path.to_str().unwrap().to_owned();
+                let res_file_path = get_res_path_from_ast(&ast_file_path);
+                if let Some(res_file_path) = res_file_path {
+                    let _ = ast_modules.insert(
+                        res_file_path.to_owned(),
+                        (
+                            module_name,
+                            package.name.to_owned(),
+                            package.namespace.to_owned(),
+                        ),
+                    );
+                    let _ = ast_rescript_file_locations.insert(res_file_path);
                 }
-                Err(_) => (),
             }
         }
     }
@@ -187,11 +397,11 @@ pub fn cleanup_previous_build(build_state: &mut BuildState) -> (usize, usize, AH
             helpers::canonicalize_parent_string_path(rescript_file_location)
         })
         .collect::<AHashSet<String>>();
-    // delete the .mjs file which appear in our previous compile assets
+    // delete the JavaScript files which appear in our previous compile assets
     // but does not exists anymore
     // delete the compiler assets for which modules we can't find a rescript file
     // location of rescript file is in the AST
-    // delete the .mjs file for which we DO have a compiler asset, but don't have a
+    // delete the JavaScript files for which we DO have a compiler asset, but don't have a
     // rescript file anymore (path is found in the .ast file)
     let diff = ast_rescript_file_locations
         .difference(&canonicalized_rescript_file_locations)
@@ -199,112 +409,29 @@ pub fn cleanup_previous_build(build_state: &mut BuildState) -> (usize, usize, AH
 
     let diff_len = diff.len();
 
+    let root_package = build_state
+        .get_package(&build_state.root_config_name)
+        .expect("Root package not found");
     diff.par_iter().for_each(|canonicalized_res_file_location| {
-        let (_module_name, package_name, package_namespace, _last_modified, _ast_file_path) =
-            ast_modules
-                .get(&canonicalized_res_file_location.to_string())
-                .expect("Could not find module name for ast file");
-
-        remove_asts(
-            canonicalized_res_file_location,
-            package_name,
-            &build_state.project_root,
-        );
-        remove_compile_assets(
-            canonicalized_res_file_location,
-            package_name,
-            package_namespace,
-            &build_state.project_root,
-        );
-        remove_mjs_file(&canonicalized_res_file_location)
-    });
+        let (_module_name, package_name, package_namespace) = ast_modules
+            .get(&canonicalized_res_file_location.to_string())
+            .expect("Could not find module name for ast file");
 
-    ast_rescript_file_locations
-        .intersection(&canonicalized_rescript_file_locations)
-        .into_iter()
-        .for_each(|canonicalized_res_file_location| {
-            let (module_name, _package_name, package_namespace, ast_last_modified, ast_file_path) =
-                ast_modules
-                    .get(canonicalized_res_file_location)
-                    .expect("Could not find module name for ast file");
-            let module = build_state
-                .modules
-                .get_mut(module_name)
-                .expect("Could not find module for ast file");
-            let full_module_name =
-                helpers::module_name_with_namespace(module_name, &package_namespace);
-
-            let compile_dirty = cmi_modules.get(&full_module_name);
-            if let Some(compile_dirty) = compile_dirty {
-                // println!("{} is not dirty", module_name);
-                let (implementation_last_modified, interface_last_modified) = match &module
-                    .source_type
-                {
-                    SourceType::MlMap(_) => (None, None),
-                    SourceType::SourceFile(source_file) => {
-                        let implementation_last_modified = source_file.implementation.last_modified;
-                        let interface_last_modified = source_file
-                            .interface
-                            .as_ref()
-                            .map(|interface| interface.last_modified);
-                        (Some(implementation_last_modified), interface_last_modified)
-                    }
-                };
-                let last_modified = match (implementation_last_modified, interface_last_modified) {
-                    (Some(implementation_last_modified), Some(interface_last_modified)) => {
-                        if implementation_last_modified > interface_last_modified {
-                            Some(implementation_last_modified)
-                        } else {
-                            Some(interface_last_modified)
-                        }
-                    }
-                    (Some(implementation_last_modified), None) => {
-                        Some(implementation_last_modified)
-                    }
-                    _ => None,
-                };
-
-                if let Some(last_modified) = last_modified {
-                    if compile_dirty > &last_modified {
-                        module.compile_dirty = false;
-                    }
-                }
-            }
-
-            match &mut module.source_type {
-                SourceType::MlMap(_) => unreachable!("MlMap is not matched with a ReScript file"),
-                SourceType::SourceFile(source_file) => {
-                    if helpers::is_interface_ast_file(ast_file_path) {
-                        let interface = source_file
-                            .interface
-                            .as_mut()
-                            .expect("Could not find interface for module");
-
-                        let source_last_modified = interface.last_modified;
-                        if ast_last_modified > &source_last_modified {
-                            interface.dirty = false;
-                        }
-                    } else {
-                        let implementation = &mut source_file.implementation;
-                        let source_last_modified = implementation.last_modified;
-                        if ast_last_modified > &source_last_modified {
-                            implementation.dirty = false;
-                        }
-                    }
-                }
-            }
-        });
+        let package = build_state
+            .packages
+            .get(package_name)
+            .expect("Package not found");
+        remove_asts(canonicalized_res_file_location, package);
+        remove_compile_assets(canonicalized_res_file_location, package, package_namespace);
+        remove_js_files(canonicalized_res_file_location, package, root_package)
+    });
 
     let ast_module_names = ast_modules
         .values()
-        .map(|(module_name, _, _, _, _)| module_name)
+        .map(|(module_name, _, _)| module_name)
         .collect::<AHashSet<&String>>();
 
-    let all_module_names = build_state
-        .modules
-        .keys()
-        .map(|module_name| module_name)
-        .collect::<AHashSet<&String>>();
+    let all_module_names = build_state.modules.keys().collect::<AHashSet<&String>>();
 
     let deleted_module_names = ast_module_names
         .difference(&all_module_names)
@@ -313,7 +440,7 @@ pub fn cleanup_previous_build(build_state: &mut BuildState) -> (usize, usize, AH
             if let Some(namespace) = helpers::get_namespace_from_module_name(module_name) {
                 return namespace;
             }
-            return module_name.to_string();
+            module_name.to_string()
         })
         .collect::<AHashSet<String>>();
 
@@ -325,65 +452,55 @@ pub fn cleanup_previous_build(build_state: &mut BuildState) -> (usize, usize, AH
 }
 
 fn failed_to_parse(module: &Module) -> bool {
-    match &module.source_type {
+    matches!(
+        &module.source_type,
         SourceType::SourceFile(SourceFile {
-            implementation:
-                Implementation {
-                    parse_state: ParseState::ParseError | ParseState::Warning,
-                    ..
-                },
+            implementation: Implementation {
+                parse_state: ParseState::ParseError | ParseState::Warning,
+                ..
+            },
             ..
-        }) => true,
-        SourceType::SourceFile(SourceFile {
-            interface:
-                Some(Interface {
-                    parse_state: ParseState::ParseError | ParseState::Warning,
-                    ..
-                }),
+        }) | SourceType::SourceFile(SourceFile {
+            interface: Some(Interface {
+                parse_state: ParseState::ParseError | ParseState::Warning,
+                ..
+            }),
             ..
-        }) => true,
-        _ => false,
-    }
+        })
+    )
 }
 
 fn failed_to_compile(module: &Module) -> bool {
-    match &module.source_type {
+    matches!(
+        &module.source_type,
         SourceType::SourceFile(SourceFile {
-            implementation:
-                Implementation {
-                    compile_state: CompileState::Error | CompileState::Warning,
-                    ..
-                },
+            implementation: Implementation {
+                compile_state: CompileState::Error | CompileState::Warning,
+                ..
+            },
             ..
-        }) => true,
-        SourceType::SourceFile(SourceFile {
-            interface:
-                Some(Interface {
-                    compile_state: CompileState::Error | CompileState::Warning,
-                    ..
-                }),
+        }) | SourceType::SourceFile(SourceFile {
+            interface: Some(Interface {
+                compile_state: CompileState::Error | CompileState::Warning,
+                ..
+            }),
             ..
-        }) => true,
-        _ => false,
-    }
+        })
+    )
 }
 
-pub fn cleanup_after_build(build_state: &BuildState) {
+/// Removes the ASTs of the modules that didn't parse cleanly, and the compiler assets of the
+/// modules that didn't compile cleanly, so their warnings are shown again. Those modules are
+/// marked dirty, so a build that continues with this build state (the watcher) builds them again.
+pub fn cleanup_after_build(build_state: &mut BuildState) {
     build_state
         .modules
         .par_iter()
         .for_each(|(_module_name, module)| {
             let package = build_state.get_package(&module.package_name).unwrap();
             if failed_to_parse(module) {
-                match &module.source_type {
-                    SourceType::SourceFile(source_file) => {
-                        remove_asts(
-                            &source_file.implementation.path,
-                            &module.package_name,
-                            &build_state.project_root,
-                        );
-                    }
-                    _ => (),
+                if let SourceType::SourceFile(source_file) = &module.source_type {
+                    remove_asts(&source_file.implementation.path, package);
                 }
             }
             if failed_to_compile(module) {
@@ -397,23 +514,93 @@ pub fn cleanup_after_build(build_state: &BuildState) {
                                 &source_file.implementation.path,
                             )
                             .unwrap(),
-                            &module.package_name,
+                            package,
                             &package.namespace,
-                            &build_state.project_root,
                         );
                     }
                     SourceType::MlMap(_) => remove_compile_assets(
                         &helpers::canonicalize_string_path(&get_mlmap_path(
-                            &build_state.project_root,
-                            &module.package_name,
+                            package,
                             &package.namespace.to_suffix().unwrap(),
                         ))
                         .unwrap(),
-                        &module.package_name,
+                        package,
                         &package_tree::Namespace::NoNamespace,
-                        &build_state.project_root,
                     ),
                 }
             }
         });
+    build_state.modules.values_mut().for_each(|module| {
+        if failed_to_parse(module) {
+            if let SourceType::SourceFile(ref mut source_file) = module.source_type {
+                source_file.implementation.dirty = true;
+                if let Some(interface) = source_file.interface.as_mut() {
+                    interface.dirty = true;
+                }
+            }
+        }
+        if failed_to_compile(module) {
+            module.compile_dirty = true;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// A package in `dir` with the given package specs, and the JavaScript files in it
+    fn write_package(dir: &Path, package_specs: &str, js_files: &[&str]) -> package_tree::Package {
+        let bsconfig = serde_json::from_str(&format!(
+            r#"{{"name": "test", "sources": "src", "package-specs": {}}}"#,
+            package_specs
+        ))
+        .unwrap();
+        for js_file in js_files {
+            let path = dir.join(js_file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        package_tree::make_package(bsconfig, dir.to_str().unwrap(), false, true)
+    }
+
+    #[test]
+    fn test_removes_out_of_source_js_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let package = write_package(
+            dir,
+            r#"["commonjs", "es6", "es6-global"]"#,
+            &[
+                "lib/js/src/nested/App.mjs",
+                "lib/es6/src/nested/App.mjs",
+                "lib/es6_global/src/nested/App.mjs",
+                "lib/js/src/nested/Other.mjs",
+            ],
+        );
+        let source_file = dir.join("src/nested/App.res");
+
+        remove_js_files(source_file.to_str().unwrap(), &package, &package);
+        assert!(!dir.join("lib/js/src/nested/App.mjs").exists());
+        assert!(!dir.join("lib/es6/src/nested/App.mjs").exists());
+        assert!(!dir.join("lib/es6_global/src/nested/App.mjs").exists());
+        assert!(dir.join("lib/js/src/nested/Other.mjs").exists());
+    }
+
+    #[test]
+    fn test_removes_bs_js_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let package = write_package(
+            dir,
+            r#"{"module": "commonjs", "in-source": true, "suffix": ".bs.js"}"#,
+            &["src/App.bs.js", "src/App.mjs"],
+        );
+        let source_file = dir.join("src/App.res");
+
+        remove_js_files(source_file.to_str().unwrap(), &package, &package);
+        assert!(!dir.join("src/App.bs.js").exists());
+        assert!(dir.join("src/App.mjs").exists());
+    }
 }
@@ -0,0 +1,222 @@
+//! The errors that stop a build. Every category maps to its own exit code, so scripts and CI can
+//! tell a broken configuration apart from a type error in the sources.
+
+use std::fmt;
+use std::io;
+
+/// Something wrong with the packages in the `bs-dependencies` of a rescript.json or bsconfig.json
+#[derive(Debug, Clone, PartialEq)]
+pub enum PackageProblem {
+    /// A dependency that isn't installed
+    Missing {
+        package_name: String,
+        dependent: String,
+        path: String,
+    },
+    /// A dependency that is installed, but isn't a ReScript package
+    MissingConfig {
+        package_name: String,
+        dependent: String,
+        path: String,
+    },
+    /// A dependency with a different name in its config than the name it's installed as, `path` is
+    /// the config file
+    NameMismatch {
+        name: String,
+        folder_name: String,
+        path: String,
+    },
+    /// Packages that depend on each other, the first package is repeated at the end
+    Cycle { packages: Vec<String> },
+}
+
+impl fmt::Display for PackageProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PackageProblem::Missing {
+                package_name,
+                dependent,
+                path,
+            } => write!(
+                f,
+                "Could not find package \"{}\" (a dependency of \"{}\") in {}. Did you install the dependencies?",
+                package_name, dependent, path
+            ),
+            PackageProblem::MissingConfig {
+                package_name,
+                dependent,
+                path,
+            } => write!(
+                f,
+                "Package \"{}\" (a dependency of \"{}\") has no rescript.json or bsconfig.json in {}",
+                package_name, dependent, path
+            ),
+            PackageProblem::NameMismatch {
+                name,
+                folder_name,
+                path,
+            } => write!(
+                f,
+                "The package is called \"{}\" in {}, but it's a dependency as \"{}\"",
+                name, path, folder_name
+            ),
+            PackageProblem::Cycle { packages } => write!(
+                f,
+                "These packages depend on each other: {}",
+                packages.join(" -> ")
+            ),
+        }
+    }
+}
+
+/// `module_name` depends on `dependency` in `file`, a step in a dependency cycle for instance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DependencyStep {
+    pub module_name: String,
+    pub dependency: String,
+    pub file: String,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// A bsconfig.json that can't be read or parsed
+    Config {
+        path: String,
+        message: String,
+    },
+    /// Problems with the dependencies between packages, they are all reported at once
+    Packages {
+        problems: Vec<PackageProblem>,
+    },
+    Io {
+        context: String,
+        error: io::Error,
+    },
+    /// The compiler can't be found or can't be started, or it produced something we can't read
+    Compiler {
+        message: String,
+    },
+    UnsupportedPlatform {
+        os: String,
+        arch: String,
+    },
+    DuplicateModule {
+        module_name: String,
+        first_path: String,
+        second_path: String,
+    },
+    /// Modules that depend on each other, every cycle starts and ends at the same module
+    DependencyCycle {
+        cycles: Vec<Vec<DependencyStep>>,
+    },
+    /// Modules that use a module of a dev source, while they are not in a dev source of the same
+    /// package themselves
+    DevDependency {
+        dependencies: Vec<DependencyStep>,
+    },
+    /// The build was stopped halfway, because the watcher has newer changes to build
+    Cancelled,
+    /// Some source files have syntax errors, they are reported when they are parsed
+    Parse,
+    /// Some modules have type errors, they are reported when they are compiled
+    Compile,
+}
+
+impl Error {
+    /// 1 is used for errors in the sources, 2 is what clap exits with when the command line is
+    /// invalid.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Parse | Error::Compile => 1,
+            Error::Config { .. } => 3,
+            Error::Packages { .. } => 4,
+            Error::Io { .. } => 5,
+            Error::Compiler { .. } | Error::UnsupportedPlatform { .. } => 6,
+            Error::DuplicateModule { .. } => 7,
+            Error::DependencyCycle { .. } => 8,
+            Error::DevDependency { .. } => 9,
+            // only the watcher cancels builds, and it keeps running afterwards
+            Error::Cancelled => 130,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config { path, message } => {
+                write!(f, "Could not read {}: {}", path, message)
+            }
+            Error::Packages { problems } => {
+                write!(f, "Can't continue, found problems with the packages:")?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+            Error::Io { context, error } => write!(f, "{}: {}", context, error),
+            Error::Compiler { message } => write!(f, "{}", message),
+            Error::UnsupportedPlatform { os, arch } => write!(
+                f,
+                "The ReScript compiler is not available for this platform ({} {})",
+                os, arch
+            ),
+            Error::DuplicateModule {
+                module_name,
+                first_path,
+                second_path,
+            } => write!(
+                f,
+                "Duplicate files found for module {}:\n  {}\n  {}",
+                module_name, first_path, second_path
+            ),
+            Error::DependencyCycle { cycles } => {
+                write!(f, "Can't continue, found a dependency cycle")?;
+                for cycle in cycles {
+                    write!(f, "\n\n  ")?;
+                    for step in cycle {
+                        write!(f, "{} -> ", step.module_name)?;
+                    }
+                    if let Some(step) = cycle.last() {
+                        write!(f, "{}", step.dependency)?;
+                    }
+                    for step in cycle {
+                        write!(
+                            f,
+                            "\n    {} -> {} in {}",
+                            step.module_name, step.dependency, step.file
+                        )?;
+                    }
+                }
+                Ok(())
+            }
+            Error::DevDependency { dependencies } => {
+                write!(
+                    f,
+                    "Can't continue, these modules use modules of dev sources, which is only \
+                     possible from a dev source of the same package:"
+                )?;
+                for step in dependencies {
+                    write!(
+                        f,
+                        "\n  {} -> {} in {}",
+                        step.module_name, step.dependency, step.file
+                    )?;
+                }
+                Ok(())
+            }
+            Error::Cancelled => write!(f, "The build was cancelled"),
+            Error::Parse => write!(f, "Could not parse the source files"),
+            Error::Compile => write!(f, "Could not compile the source files"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
@@ -1,5 +1,7 @@
+use crate::bsconfig;
+use crate::error::Error;
 use crate::package_tree;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::io::{self, BufRead};
 use std::path::{Component, Path, PathBuf};
@@ -35,23 +37,24 @@ impl LexicalAbsolute for Path {
                 Component::ParentDir => {
                     absolute.pop();
                 }
-                component @ _ => absolute.push(component.as_os_str()),
+                component => absolute.push(component.as_os_str()),
             }
         }
         Ok(absolute)
     }
 }
 
-pub fn get_package_path(root: &str, package_name: &str) -> String {
-    format!("{}/node_modules/{}", root, package_name)
-}
-
-pub fn get_build_path(root: &str, package_name: &str) -> String {
-    format!("{}/node_modules/{}/lib/ocaml", root, package_name)
-}
-
-pub fn get_bs_build_path(root: &str, package_name: &str) -> String {
-    format!("{}/node_modules/{}/lib/bs", root, package_name)
+/// Finds the folder of a package the way Node does: in the `node_modules` folder of `from_dir`, or
+/// in the one of the closest parent folder that has the package. This finds packages in nested
+/// `node_modules`, in workspaces that aren't hoisted and in the `.pnpm` layout. The folder is
+/// canonicalized, so a package that is linked in several places is always found at the same path.
+pub fn resolve_package(package_name: &str, from_dir: &str) -> Option<String> {
+    Path::new(from_dir)
+        .ancestors()
+        .filter(|dir| dir.file_name() != Some(OsStr::new("node_modules")))
+        .map(|dir| dir.join("node_modules").join(package_name))
+        .find(|package_dir| package_dir.is_dir())
+        .and_then(|package_dir| canonicalize_string_path(package_dir.to_str()?))
 }
 
 pub fn get_path(root: &str, package_name: &str, file: &str) -> String {
@@ -65,31 +68,31 @@ pub fn get_node_modules_path(root: &str) -> String {
 pub fn get_abs_path(path: &str) -> String {
     let abs_path_buf = PathBuf::from(path);
 
-    return abs_path_buf
+    abs_path_buf
         .to_lexical_absolute()
         .expect("Could not canonicalize")
         .to_str()
         .expect("Could not canonicalize")
-        .to_string();
+        .to_string()
 }
 
 pub fn get_basename(path: &str) -> String {
     let path_buf = PathBuf::from(path);
-    return path_buf
+    path_buf
         .file_stem()
         .expect("Could not get basename")
         .to_str()
         .expect("Could not get basename")
-        .to_string();
+        .to_string()
 }
 
 pub fn change_extension(path: &str, new_extension: &str) -> String {
     let path_buf = PathBuf::from(path);
-    return path_buf
+    path_buf
         .with_extension(new_extension)
         .to_str()
         .expect("Could not change extension")
-        .to_string();
+        .to_string()
 }
 
 /// Capitalizes the first character in s.
@@ -111,7 +114,7 @@ fn add_suffix(base: &str, namespace: &package_tree::Namespace) -> String {
         | package_tree::Namespace::NamespaceWithEntry {
             namespace,
             entry: _,
-        } => base.to_string() + "-" + &namespace,
+        } => base.to_string() + "-" + namespace,
         package_tree::Namespace::NoNamespace => base.to_string(),
     }
 }
@@ -143,7 +146,7 @@ pub fn contains_ascii_characters(str: &str) -> bool {
             return true;
         }
     }
-    return false;
+    false
 }
 
 pub fn create_build_path(build_path: &str) {
@@ -153,19 +156,26 @@ pub fn create_build_path(build_path: &str) {
         .unwrap();
 }
 
-pub fn get_bsc(root_path: &str) -> String {
+pub fn get_bsc(root_path: &str) -> Result<String, Error> {
     let subfolder = match (std::env::consts::OS, std::env::consts::ARCH) {
         ("macos", "aarch64") => "darwinarm64",
         ("macos", _) => "darwin",
         ("linux", _) => "linux",
         ("windows", _) => "win32",
-        _ => panic!("Unsupported architecture"),
+        (os, arch) => {
+            return Err(Error::UnsupportedPlatform {
+                os: os.to_string(),
+                arch: arch.to_string(),
+            })
+        }
     };
 
-    get_node_modules_path(root_path) + "/rescript/" + subfolder + "/bsc.exe"
+    let rescript_dir = resolve_package("rescript", root_path)
+        .unwrap_or_else(|| get_node_modules_path(root_path) + "/rescript");
+    Ok(rescript_dir + "/" + subfolder + "/bsc.exe")
 }
 
-pub fn string_ends_with_any(s: &PathBuf, suffixes: &[&str]) -> bool {
+pub fn string_ends_with_any(s: &Path, suffixes: &[&str]) -> bool {
     suffixes.iter().any(|&suffix| {
         s.extension()
             .unwrap_or(&OsString::new())
@@ -177,12 +187,11 @@ pub fn string_ends_with_any(s: &PathBuf, suffixes: &[&str]) -> bool {
 
 pub fn get_compiler_asset(
     source_file: &str,
-    package_name: &str,
+    package: &package_tree::Package,
     namespace: &package_tree::Namespace,
-    root_path: &str,
     extension: &str,
 ) -> String {
-    get_build_path(root_path, package_name)
+    package.get_build_path()
         + "/"
         + &file_path_to_compiler_asset_basename(source_file, namespace)
         + "."
@@ -190,16 +199,16 @@ pub fn get_compiler_asset(
 }
 
 pub fn canonicalize_string_path(path: &str) -> Option<String> {
-    return Path::new(path)
+    Path::new(path)
         .canonicalize()
         .ok()
-        .map(|path| path.to_str().expect("Could not canonicalize").to_string());
+        .map(|path| path.to_str().expect("Could not canonicalize").to_string())
 }
 
 // sometimes we only want to canonicalize the parent path, if we potentially want to
 // canonicalize file paths that might not exist anymore BUT the parent path does
 pub fn canonicalize_parent_string_path(path: &str) -> Option<String> {
-    return Path::new(path)
+    Path::new(path)
         .parent()
         .unwrap()
         .canonicalize()
@@ -210,15 +219,14 @@ pub fn canonicalize_parent_string_path(path: &str) -> Option<String> {
                 .expect("There should always be a filename");
             // add back file
             let path = dir.join(filename);
-            return path.to_str().expect("Could not canonicalize").to_string();
-        });
+            path.to_str().expect("Could not canonicalize").to_string()
+        })
 }
 
 pub fn get_bs_compiler_asset(
     source_file: &str,
-    package_name: &str,
+    package: &package_tree::Package,
     namespace: &package_tree::Namespace,
-    root_path: &str,
     extension: &str,
 ) -> String {
     let namespace = match extension {
@@ -226,8 +234,7 @@ pub fn get_bs_compiler_asset(
         _ => namespace,
     };
     let canonicalized_source_file = source_file;
-    let canonicalized_path =
-        canonicalize_string_path(&get_package_path(root_path, &package_name)).unwrap();
+    let canonicalized_path = canonicalize_string_path(&package.package_dir).unwrap();
 
     let dir = std::path::Path::new(&canonicalized_source_file)
         .strip_prefix(canonicalized_path)
@@ -235,7 +242,7 @@ pub fn get_bs_compiler_asset(
         .parent()
         .unwrap();
 
-    std::path::Path::new(&get_bs_build_path(root_path, &package_name))
+    std::path::Path::new(&package.get_bs_build_path())
         .join(dir)
         .join(file_path_to_compiler_asset_basename(source_file, namespace) + extension)
         .to_str()
@@ -243,6 +250,60 @@ pub fn get_bs_compiler_asset(
         .to_owned()
 }
 
+/// The folder of a source file, relative to the package. Source files are either relative to the
+/// folder of the package as it was found, or to its canonicalized folder.
+fn get_package_relative_dir(source_file: &str, package: &package_tree::Package) -> PathBuf {
+    let source_file = Path::new(source_file);
+    let relative_file = source_file
+        .strip_prefix(&package.package_dir)
+        .ok()
+        .or_else(|| {
+            canonicalize_string_path(&package.package_dir)
+                .and_then(|package_dir| source_file.strip_prefix(package_dir).ok())
+        })
+        .expect("Source file is not in its package");
+    relative_file.parent().unwrap().to_path_buf()
+}
+
+/// The folder, relative to the package, that bsc writes the JavaScript of a source file to for a
+/// package spec: next to the source file, or in the same folder under `lib/js` or `lib/es6`.
+pub fn get_package_output_dir(
+    source_file: &str,
+    package: &package_tree::Package,
+    package_spec: &bsconfig::QualifiedPackageSpec,
+) -> String {
+    let source_dir = get_package_relative_dir(source_file, package);
+    let output_dir = if package_spec.in_source {
+        source_dir
+    } else {
+        Path::new(package_spec.get_out_of_source_dir()).join(source_dir)
+    };
+    output_dir.to_str().unwrap().to_string()
+}
+
+/// The JavaScript files that are generated for a source file, one for every package spec of the
+/// root package.
+pub fn get_js_paths(
+    source_file: &str,
+    package: &package_tree::Package,
+    root_package: &package_tree::Package,
+) -> Vec<String> {
+    let mut js_paths: Vec<String> = vec![];
+    for package_spec in bsconfig::get_package_specs(&root_package.bsconfig) {
+        let suffix = bsconfig::get_suffix(&package_spec, &package.bsconfig, &root_package.bsconfig);
+        let js_path = Path::new(&package.package_dir)
+            .join(get_package_output_dir(source_file, package, &package_spec))
+            .join(get_basename(source_file) + &suffix.to_string())
+            .to_str()
+            .unwrap()
+            .to_string();
+        if !js_paths.contains(&js_path) {
+            js_paths.push(js_path);
+        }
+    }
+    js_paths
+}
+
 pub fn get_namespace_from_module_name(module_name: &str) -> Option<String> {
     let mut split = module_name.split("-");
     let _ = split.next();
@@ -253,30 +314,28 @@ pub fn is_interface_ast_file(file: &str) -> bool {
     file.ends_with(".iast")
 }
 
-pub fn get_mlmap_path(root_path: &str, package_name: &str, namespace: &str) -> String {
-    get_build_path(root_path, package_name) + "/" + namespace + ".mlmap"
+pub fn get_mlmap_path(package: &package_tree::Package, namespace: &str) -> String {
+    package.get_build_path() + "/" + namespace + ".mlmap"
 }
 
-pub fn get_mlmap_compile_path(root_path: &str, package_name: &str, namespace: &str) -> String {
-    get_build_path(root_path, package_name) + "/" + namespace + ".cmi"
+pub fn get_mlmap_compile_path(package: &package_tree::Package, namespace: &str) -> String {
+    package.get_build_path() + "/" + namespace + ".cmi"
 }
 
-pub fn get_ast_path(source_file: &str, package_name: &str, root_path: &str) -> String {
+pub fn get_ast_path(source_file: &str, package: &package_tree::Package) -> String {
     get_compiler_asset(
         source_file,
-        package_name,
+        package,
         &package_tree::Namespace::NoNamespace,
-        root_path,
         "ast",
     )
 }
 
-pub fn get_iast_path(source_file: &str, package_name: &str, root_path: &str) -> String {
+pub fn get_iast_path(source_file: &str, package: &package_tree::Package) -> String {
     get_compiler_asset(
         source_file,
-        package_name,
+        package,
         &package_tree::Namespace::NoNamespace,
-        root_path,
         "iast",
     )
 }
@@ -286,6 +345,13 @@ pub fn read_lines(filename: String) -> io::Result<io::Lines<io::BufReader<fs::Fi
     Ok(io::BufReader::new(file).lines())
 }
 
+pub fn compute_file_hash(path: &str) -> Option<blake3::Hash> {
+    match fs::read(path) {
+        Ok(str) => Some(blake3::hash(&str)),
+        Err(_) => None,
+    }
+}
+
 pub fn get_system_time() -> u128 {
     let start = SystemTime::now();
     let since_the_epoch = start
@@ -295,19 +361,57 @@ pub fn get_system_time() -> u128 {
 }
 
 pub fn is_interface_file(extension: &str) -> bool {
-    match extension {
-        "resi" | "mli" | "rei" => true,
-        _ => false,
-    }
+    matches!(extension, "resi" | "mli" | "rei")
 }
 
 pub fn is_implementation_file(extension: &str) -> bool {
-    match extension {
-        "res" | "ml" | "re" => true,
-        _ => false,
-    }
+    matches!(extension, "res" | "ml" | "re")
 }
 
 pub fn is_source_file(extension: &str) -> bool {
     is_interface_file(extension) || is_implementation_file(extension)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(fields: &str) -> package_tree::Package {
+        let bsconfig = serde_json::from_str(&format!(
+            r#"{{"name": "test", "sources": "src"{}}}"#,
+            fields
+        ))
+        .unwrap();
+        package_tree::make_package(bsconfig, "/project", false, true)
+    }
+
+    #[test]
+    fn test_get_js_paths_dedups_in_source_specs() {
+        let root_package = package(
+            r#", "package-specs": [{"module": "es6", "in-source": true}, {"module": "commonjs", "in-source": true}]"#,
+        );
+        assert_eq!(
+            get_js_paths("/project/src/App.res", &root_package, &root_package),
+            vec!["/project/src/App.mjs"]
+        );
+    }
+
+    #[test]
+    fn test_get_js_paths_mirrors_the_sources_out_of_source() {
+        let root_package = package(r#", "package-specs": ["commonjs", "es6", "es6-global"]"#);
+        let package_spec = bsconfig::get_package_specs(&root_package.bsconfig)[0].to_owned();
+        assert_eq!(package_spec.get_out_of_source_dir(), "lib/js");
+        assert_eq!(
+            get_package_output_dir("/project/src/nested/App.res", &root_package, &package_spec),
+            "lib/js/src/nested"
+        );
+        assert_eq!(
+            get_js_paths("/project/src/nested/App.res", &root_package, &root_package),
+            vec![
+                "/project/lib/js/src/nested/App.mjs",
+                "/project/lib/es6/src/nested/App.mjs",
+                "/project/lib/es6_global/src/nested/App.mjs",
+            ]
+        );
+    }
+}
@@ -2,6 +2,7 @@ pub mod bsconfig;
 pub mod build;
 pub mod build_types;
 pub mod clean;
+pub mod error;
 pub mod helpers;
 pub mod logs;
 pub mod package_tree;
@@ -49,7 +49,7 @@ pub fn initialize(packages: &AHashMap<String, Package>) {
         let _ = File::create(get_log_file_path(Location::Bs, name)).map(|file| {
             write_to_log_file(
                 file,
-                &name,
+                name,
                 &format!("#Start({})\n", helpers::get_system_time()),
             )
         });
@@ -60,7 +60,7 @@ pub fn append(name: &str, str: &str) {
     let _ = File::options()
         .append(true)
         .open(get_log_file_path(Location::Bs, name))
-        .map(|file| write_to_log_file(file, &name, str));
+        .map(|file| write_to_log_file(file, name, str));
 }
 
 pub fn finalize(packages: &AHashMap<String, Package>) {
@@ -71,11 +71,14 @@ pub fn finalize(packages: &AHashMap<String, Package>) {
             .map(|file| {
                 write_to_log_file(
                     file,
-                    &name,
+                    name,
                     &format!("#Done({})\n", helpers::get_system_time()),
                 )
             });
 
-        let _ = std::fs::copy(get_log_file_path(Location::Bs, name), get_log_file_path(Location::Ocaml, name));
+        let _ = std::fs::copy(
+            get_log_file_path(Location::Bs, name),
+            get_log_file_path(Location::Ocaml, name),
+        );
     })
 }
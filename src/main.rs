@@ -1,36 +1,183 @@
+use clap::{ArgAction, Parser, Subcommand};
+use log::LevelFilter;
 use regex::Regex;
+use std::path::PathBuf;
 pub mod bsconfig;
 pub mod build;
 pub mod build_types;
 pub mod clean;
+pub mod error;
 pub mod helpers;
 pub mod logs;
 pub mod package_tree;
 pub mod queue;
 pub mod watcher;
 
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The folder where the root rescript.json or bsconfig.json lives
+    #[arg(long, global = true)]
+    folder: Option<String>,
+
+    /// Only build the source files with a name that matches this regex
+    #[arg(long, global = true, value_parser = parse_regex)]
+    filter: Option<Regex>,
+
+    /// The number of modules that are compiled in parallel, defaults to the number of CPUs
+    #[arg(short, long, global = true, value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+
+    /// Print 0.00s instead of the actual timings, useful for snapshot tests
+    #[arg(long, global = true)]
+    no_timing: bool,
+
+    /// Log more details about the build, repeat for even more (-vv)
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Only print warnings and errors
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Store the outputs of the compiled modules in this folder, and restore them from it instead
+    /// of compiling them again. Defaults to $REWATCH_CACHE_DIR
+    #[arg(long, global = true, value_name = "PATH")]
+    cache_dir: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Build the project (the default)
+    Build(BuildArgs),
+    /// Build the project, and rebuild it when a source file changes
+    Watch(WatchArgs),
+    /// Remove all the compiler assets and generated JavaScript
+    Clean(FolderArg),
+}
+
+#[derive(clap::Args, Debug, Clone, Default)]
+struct FolderArg {
+    /// Shorthand for --folder
+    #[arg(value_name = "FOLDER", conflicts_with = "folder")]
+    folder_path: Option<String>,
+}
+
+#[derive(clap::Args, Debug, Clone, Default)]
+struct BuildArgs {
+    #[command(flatten)]
+    folder: FolderArg,
+
+    /// Print a JSON report of the build to stdout, instead of the progress
+    #[arg(long, conflicts_with = "report")]
+    json: bool,
+
+    /// Write a JSON report of the build to this file
+    #[arg(long, value_name = "PATH")]
+    report: Option<PathBuf>,
+
+    /// Write the warnings and errors of the build to this file, as a SARIF 2.1.0 log
+    #[arg(long, value_name = "PATH")]
+    sarif: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct WatchArgs {
+    #[command(flatten)]
+    folder: FolderArg,
+
+    /// Wait until no file changed for this many milliseconds, before building the changes
+    #[arg(long, value_name = "MS", default_value_t = 300)]
+    debounce: u64,
+}
+
+fn parse_regex(filter: &str) -> Result<Regex, String> {
+    Regex::new(filter).map_err(|e| e.to_string())
+}
+
 fn main() {
-    env_logger::init();
-    let command = std::env::args().nth(1).unwrap_or("build".to_string());
-    let folder = std::env::args().nth(2).unwrap_or(".".to_string());
-    let filter = std::env::args()
-        .nth(3)
-        .map(|filter| Regex::new(filter.as_ref()).expect("Could not parse regex"));
-
-    match command.as_str() {
-        "clean" => {
-            build::clean(&folder);
+    let args = Args::parse();
+
+    let mut logger = env_logger::Builder::from_default_env();
+    match (args.verbose, args.quiet) {
+        (0, false) => (),
+        (0, true) => {
+            logger.filter_level(LevelFilter::Error);
         }
-        "build" => {
-            match build::build(&filter, &folder) {
-                Err(()) => std::process::exit(1),
-                Ok(_) => std::process::exit(0),
+        (1, _) => {
+            logger.filter_level(LevelFilter::Info);
+        }
+        _ => {
+            logger.filter_level(LevelFilter::Debug);
+        }
+    };
+    logger.init();
+
+    if let Some(jobs) = args.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs.into())
+            .build_global()
+            .expect("Could not set the number of jobs");
+    }
+
+    let command = args.command.unwrap_or(Command::Build(BuildArgs::default()));
+    let folder_arg = match &command {
+        Command::Build(BuildArgs {
+            folder: folder_arg, ..
+        })
+        | Command::Watch(WatchArgs {
+            folder: folder_arg, ..
+        })
+        | Command::Clean(folder_arg) => folder_arg,
+    };
+    let folder = folder_arg
+        .folder_path
+        .to_owned()
+        .or(args.folder)
+        .unwrap_or(".".to_string());
+    let show_progress = !args.quiet;
+    let cache = args
+        .cache_dir
+        .map(build::cache::Cache::new)
+        .or_else(build::cache::Cache::from_env);
+
+    let result = match command {
+        Command::Clean(_) => build::clean(&folder, show_progress, args.no_timing),
+        Command::Build(build_args) => {
+            let report_target = match (build_args.json, build_args.report) {
+                (true, _) => Some(build::report::Target::Stdout),
+                (false, Some(path)) => Some(build::report::Target::File(path)),
+                (false, None) => None,
             };
+            build::build(
+                &args.filter,
+                &folder,
+                show_progress,
+                args.no_timing,
+                report_target.as_ref(),
+                build_args.sarif.as_deref(),
+                cache.as_ref(),
+            )
+            .map(|_| ())
         }
-        "watch" => {
-            let _modules = build::build(&filter, &folder);
-            watcher::start(&filter, &folder);
+        Command::Watch(watch_args) => {
+            watcher::start(
+                &args.filter,
+                &folder,
+                show_progress,
+                args.no_timing,
+                std::time::Duration::from_millis(watch_args.debounce),
+                cache.as_ref(),
+            );
+            Ok(())
         }
-        _ => println!("Not a valid build command"),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(e.exit_code());
     }
 }
@@ -1,5 +1,7 @@
 use crate::bsconfig;
 use crate::bsconfig::*;
+use crate::build::cycles;
+use crate::error::{Error, PackageProblem};
 use crate::helpers;
 use crate::helpers::{is_source_file, LexicalAbsolute};
 use ahash::{AHashMap, AHashSet};
@@ -13,9 +15,11 @@ use std::{error, fs};
 #[derive(Debug, Clone)]
 pub struct SourceFileMeta {
     pub modified: SystemTime,
+    /// whether the file is in a source with the type 'dev'
+    pub is_type_dev: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Namespace {
     Namespace(String),
     NamespaceWithEntry { namespace: String, entry: String },
@@ -47,6 +51,20 @@ pub struct Package {
     pub package_dir: String,
     pub dirs: Option<AHashSet<PathBuf>>,
     pub is_pinned_dep: bool,
+    /// the root package, or a package of the project that is not installed in a node_modules
+    /// folder, only these get their dev sources and `bs-dev-dependencies` compiled
+    pub is_local_dep: bool,
+}
+
+impl Package {
+    /// Where bsc writes the compiler assets of the package
+    pub fn get_build_path(&self) -> String {
+        format!("{}/lib/ocaml", self.package_dir)
+    }
+
+    pub fn get_bs_build_path(&self) -> String {
+        format!("{}/lib/bs", self.package_dir)
+    }
 }
 
 impl PartialEq for Package {
@@ -57,7 +75,7 @@ impl PartialEq for Package {
 impl Eq for Package {}
 impl Hash for Package {
     fn hash<H: Hasher>(&self, _state: &mut H) {
-        blake3::hash(&self.name.as_bytes());
+        blake3::hash(self.name.as_bytes());
     }
 }
 
@@ -68,10 +86,27 @@ fn matches_filter(filter: &Option<regex::Regex>, path: &str) -> bool {
     }
 }
 
+/// Whether a folder, or one of the folders it's in, is in the `ignored-dirs` of the package. Like
+/// the stock build system, the ignored folders are paths relative to the package.
+fn is_ignored_dir(package: &Package, dir: &Path) -> bool {
+    let ignored_dirs = match &package.bsconfig.ignored_dirs {
+        Some(ignored_dirs) if !ignored_dirs.is_empty() => ignored_dirs,
+        _ => return false,
+    };
+    let relative_dir = dir.strip_prefix(&package.package_dir).unwrap_or(dir);
+    relative_dir.ancestors().any(|ancestor| {
+        ignored_dirs
+            .iter()
+            .any(|ignored_dir| Path::new(ignored_dir.trim_start_matches("./")) == ancestor)
+    })
+}
+
 pub fn read_folders(
     filter: &Option<regex::Regex>,
+    package: &Package,
     path: &Path,
     recurse: bool,
+    is_type_dev: bool,
 ) -> Result<AHashMap<String, SourceFileMeta>, Box<dyn error::Error>> {
     let mut map: AHashMap<String, SourceFileMeta> = AHashMap::new();
     let path_buf = PathBuf::from(path);
@@ -84,6 +119,7 @@ pub fn read_folders(
             path_lex_abs.to_str().unwrap().to_string(),
             SourceFileMeta {
                 modified: meta.modified().unwrap(),
+                is_type_dev,
             },
         )
     });
@@ -100,8 +136,8 @@ pub fn read_folders(
 
         let path_ext = entry_path_buf.extension().and_then(|x| x.to_str());
         let new_path = path_buf.join(&name);
-        if metadata.file_type().is_dir() && recurse {
-            match read_folders(&filter, &new_path, recurse) {
+        if metadata.file_type().is_dir() && recurse && !is_ignored_dir(package, &new_path) {
+            match read_folders(filter, package, &new_path, recurse, is_type_dev) {
                 Ok(s) => map.extend(s),
                 Err(e) => println!("Error reading directory: {}", e),
             }
@@ -114,6 +150,7 @@ pub fn read_folders(
                         path.to_owned() + "/" + &name,
                         SourceFileMeta {
                             modified: metadata.modified().unwrap(),
+                            is_type_dev,
                         },
                     );
                 }
@@ -156,16 +193,22 @@ fn get_source_dirs(
     };
 
     let full_path = project_root.to_string() + "/" + &package_root;
-    source_folders.insert((
-        full_path.to_owned(),
-        bsconfig::to_qualified_without_children(&source),
-    ));
+    let package_source = bsconfig::to_qualified_without_children(&source);
+    let is_type_dev = package_source.is_type_dev();
+    source_folders.insert((full_path.to_owned(), package_source));
 
     if !full_recursive {
         subdirs
             .unwrap_or(vec![])
             .par_iter()
-            .map(|subdir| get_source_dirs(&full_path, subdir.to_owned()))
+            .map(|subdir| {
+                let subdir = if is_type_dev {
+                    subdir.to_owned().into_type_dev()
+                } else {
+                    subdir.to_owned()
+                };
+                get_source_dirs(&full_path, subdir)
+            })
             .collect::<Vec<AHashSet<(String, bsconfig::PackageSource)>>>()
             .into_iter()
             .for_each(|subdir| source_folders.extend(subdir))
@@ -174,16 +217,110 @@ fn get_source_dirs(
     source_folders
 }
 
-fn get_package_dir(package_name: &str, is_root: bool, project_root: &str) -> String {
-    if is_root {
-        project_root.to_owned()
-    } else {
-        helpers::get_package_path(project_root, package_name)
+fn read_bsconfig(package_dir: &str) -> Result<bsconfig::T, Error> {
+    bsconfig::read_package_config(package_dir)
+}
+
+fn has_bsconfig(package_dir: &str) -> bool {
+    bsconfig::get_config_path(package_dir).is_some()
+}
+
+/// Dependencies are resolved to their real path, so a package of the project is one that is not in
+/// a node_modules folder, like the packages of a workspace.
+fn is_local_package(package_dir: &str) -> bool {
+    !Path::new(package_dir)
+        .components()
+        .any(|component| component.as_os_str() == "node_modules")
+}
+
+/// The packages a package depends on, with its `bs-dev-dependencies` when its dev sources are
+/// compiled.
+fn get_dependencies(bsconfig: &bsconfig::T, is_local_dep: bool) -> Vec<String> {
+    let dev_dependencies = match &bsconfig.bs_dev_dependencies {
+        Some(dev_dependencies) if is_local_dep => dev_dependencies.to_owned(),
+        _ => vec![],
+    };
+    [
+        bsconfig.bs_dependencies.to_owned().unwrap_or(vec![]),
+        dev_dependencies,
+    ]
+    .concat()
+}
+
+/// The namespace that a bsconfig configures for its package
+pub fn get_namespace(bsconfig: &bsconfig::T) -> Namespace {
+    let namespace_from_package = namespace_from_package_name(&bsconfig.name);
+    match (
+        bsconfig.namespace.to_owned(),
+        bsconfig.namespace_entry.to_owned(),
+    ) {
+        (Some(bsconfig::Namespace::Bool(false)), _) => Namespace::NoNamespace,
+        (None, _) => Namespace::NoNamespace,
+        (Some(bsconfig::Namespace::Bool(true)), None) => {
+            Namespace::Namespace(namespace_from_package)
+        }
+        (Some(bsconfig::Namespace::Bool(true)), Some(entry)) => Namespace::NamespaceWithEntry {
+            namespace: namespace_from_package,
+            entry,
+        },
+        (Some(bsconfig::Namespace::String(str)), None) => match str.as_str() {
+            "true" => Namespace::Namespace(namespace_from_package),
+            namespace if namespace.is_case(Case::UpperFlat) => {
+                Namespace::Namespace(namespace.to_string())
+            }
+            namespace => Namespace::Namespace(namespace.to_string().to_case(Case::Pascal)),
+        },
+        (Some(bsconfig::Namespace::String(str)), Some(entry)) => match str.as_str() {
+            "true" => Namespace::NamespaceWithEntry {
+                namespace: namespace_from_package,
+                entry,
+            },
+            namespace if namespace.is_case(Case::UpperFlat) => Namespace::NamespaceWithEntry {
+                namespace: namespace.to_string(),
+                entry,
+            },
+            namespace => Namespace::NamespaceWithEntry {
+                namespace: namespace.to_string().to_case(Case::Pascal),
+                entry,
+            },
+        },
     }
 }
 
-fn read_bsconfig(package_dir: &str) -> bsconfig::T {
-    bsconfig::read(package_dir.to_string() + "/bsconfig.json")
+/// A package with the source folders of its bsconfig, without its source files
+pub fn make_package(
+    bsconfig: bsconfig::T,
+    package_dir: &str,
+    is_pinned_dep: bool,
+    is_local_dep: bool,
+) -> Package {
+    let copied_bsconfig = bsconfig.to_owned();
+    let source_folders = match bsconfig.sources.to_owned() {
+        bsconfig::OneOrMore::Single(source) => get_source_dirs(package_dir, source),
+        bsconfig::OneOrMore::Multiple(sources) => {
+            let mut source_folders: AHashSet<(String, bsconfig::PackageSource)> = AHashSet::new();
+            sources
+                .iter()
+                .map(|source| get_source_dirs(package_dir, source.to_owned()))
+                .collect::<Vec<AHashSet<(String, bsconfig::PackageSource)>>>()
+                .into_iter()
+                .for_each(|source| source_folders.extend(source));
+            source_folders
+        }
+    };
+
+    Package {
+        name: copied_bsconfig.name.to_owned(),
+        bsconfig: copied_bsconfig,
+        source_folders,
+        source_files: None,
+        namespace: get_namespace(&bsconfig),
+        modules: None,
+        package_dir: package_dir.to_string(),
+        dirs: None,
+        is_pinned_dep,
+        is_local_dep,
+    }
 }
 
 /// # Make Package
@@ -193,108 +330,54 @@ fn build_package<'a>(
     map: &'a mut AHashMap<String, Package>,
     bsconfig: bsconfig::T,
     package_dir: &str,
-    // is_root: bool,
-    project_root: &str,
+    is_root: bool,
     is_pinned_dep: bool,
-    // package_name: &str,
-) -> &'a mut AHashMap<String, Package> {
-    // let (package_dir, bsconfig) = read_bsconfig(package_name, project_root, is_root);
-    let copied_bsconfig = bsconfig.to_owned();
+) -> Result<&'a mut AHashMap<String, Package>, Error> {
+    let is_local_dep = is_root || is_local_package(package_dir);
+    let dependencies = get_dependencies(&bsconfig, is_local_dep);
 
     /* At this point in time we may have started encountering elements multiple times as there is
      * no deduplication on the package level so far. Once we return this flat list of packages, do
      * have this deduplication. From that point on, we can add the source files for every single
      * one as that is an expensive operation IO wise and we don't want to duplicate that.*/
-    map.insert(copied_bsconfig.name.to_owned(), {
-        let source_folders = match bsconfig.sources.to_owned() {
-            bsconfig::OneOrMore::Single(source) => get_source_dirs(&package_dir, source),
-            bsconfig::OneOrMore::Multiple(sources) => {
-                let mut source_folders: AHashSet<(String, bsconfig::PackageSource)> =
-                    AHashSet::new();
-                sources
-                    .iter()
-                    .map(|source| get_source_dirs(&package_dir, source.to_owned()))
-                    .collect::<Vec<AHashSet<(String, bsconfig::PackageSource)>>>()
-                    .into_iter()
-                    .for_each(|source| source_folders.extend(source));
-                source_folders
-            }
-        };
-
-        let namespace_from_package = namespace_from_package_name(&bsconfig.name);
-        Package {
-            name: copied_bsconfig.name.to_owned(),
-            bsconfig: copied_bsconfig,
-            source_folders,
-            source_files: None,
-            namespace: match (bsconfig.namespace, bsconfig.namespace_entry) {
-                (Some(bsconfig::Namespace::Bool(false)), _) => Namespace::NoNamespace,
-                (None, _) => Namespace::NoNamespace,
-                (Some(bsconfig::Namespace::Bool(true)), None) => {
-                    Namespace::Namespace(namespace_from_package)
-                }
-                (Some(bsconfig::Namespace::Bool(true)), Some(entry)) => {
-                    Namespace::NamespaceWithEntry {
-                        namespace: namespace_from_package,
-                        entry: entry,
-                    }
-                }
-                (Some(bsconfig::Namespace::String(str)), None) => match str.as_str() {
-                    "true" => Namespace::Namespace(namespace_from_package),
-                    namespace if namespace.is_case(Case::UpperFlat) => {
-                        Namespace::Namespace(namespace.to_string())
-                    }
-                    namespace => Namespace::Namespace(namespace.to_string().to_case(Case::Pascal)),
-                },
-                (Some(bsconfig::Namespace::String(str)), Some(entry)) => match str.as_str() {
-                    "true" => Namespace::NamespaceWithEntry {
-                        namespace: namespace_from_package,
-                        entry,
-                    },
-                    namespace if namespace.is_case(Case::UpperFlat) => {
-                        Namespace::NamespaceWithEntry {
-                            namespace: namespace.to_string(),
-                            entry: entry,
-                        }
-                    }
-                    namespace => Namespace::NamespaceWithEntry {
-                        namespace: namespace.to_string().to_case(Case::Pascal),
-                        entry,
-                    },
-                },
-            },
-            modules: None,
-            package_dir: package_dir.to_string(),
-            dirs: None,
-            is_pinned_dep: is_pinned_dep,
-        }
-    });
-
-    bsconfig
-        .bs_dependencies
-        .to_owned()
-        .unwrap_or(vec![])
+    map.insert(
+        bsconfig.name.to_owned(),
+        make_package(
+            bsconfig.to_owned(),
+            package_dir,
+            is_pinned_dep,
+            is_local_dep,
+        ),
+    );
+
+    dependencies
         .iter()
-        .filter_map(|package_name| {
-            let package_dir = get_package_dir(package_name, false, project_root);
-            if !map.contains_key(package_name) {
-                Some(package_dir)
-            } else {
-                None
-            }
+        .filter(|package_name| !map.contains_key(*package_name))
+        // dependencies are found from the folder of the package that depends on them
+        .filter_map(|package_name| helpers::resolve_package(package_name, package_dir))
+        // a package with a different name than its folder is only read once, it's reported when
+        // the packages are validated, like the dependencies that aren't installed
+        .filter(|package_dir| {
+            has_bsconfig(package_dir)
+                && !map
+                    .values()
+                    .any(|package| &package.package_dir == package_dir)
         })
         .collect::<Vec<String>>()
         // read all bsconfig files simultanously instead of blocking
         .par_iter()
-        .map(|package_dir| (package_dir.to_owned(), read_bsconfig(package_dir)))
-        .collect::<Vec<(String, bsconfig::T)>>()
+        .map(|package_dir| {
+            read_bsconfig(package_dir)
+                .map(|child_bsconfig| (package_dir.to_owned(), child_bsconfig))
+        })
+        .collect::<Result<Vec<(String, bsconfig::T)>, Error>>()?
         .iter()
-        .fold(map, |map, (package_dir, child_bsconfig)| {
+        .try_fold(map, |map, (package_dir, child_bsconfig)| {
             build_package(
                 map,
                 child_bsconfig.to_owned(),
-                &package_dir,
-                &project_root,
+                package_dir,
+                false,
                 bsconfig
                     .pinned_dependencies
                     .as_ref()
@@ -304,6 +387,96 @@ fn build_package<'a>(
         })
 }
 
+/// Checks that every dependency of every package is installed, is a package with the name it's
+/// installed as, and that packages don't depend on each other.
+fn validate(packages: &AHashMap<String, Package>) -> Result<(), Error> {
+    let mut sorted_packages = packages.values().collect::<Vec<&Package>>();
+    sorted_packages.sort_by_key(|package| &package.name);
+
+    let mut problems = vec![];
+    let mut mismatched_dirs = AHashSet::new();
+    for package in sorted_packages {
+        for package_name in get_dependencies(&package.bsconfig, package.is_local_dep).iter() {
+            if packages.contains_key(package_name) {
+                continue;
+            }
+            let package_dir = match helpers::resolve_package(package_name, &package.package_dir) {
+                Some(package_dir) => package_dir,
+                None => {
+                    problems.push(PackageProblem::Missing {
+                        package_name: package_name.to_owned(),
+                        dependent: package.name.to_owned(),
+                        path: format!("{}/node_modules/{}", package.package_dir, package_name),
+                    });
+                    continue;
+                }
+            };
+            if !has_bsconfig(&package_dir) {
+                problems.push(PackageProblem::MissingConfig {
+                    package_name: package_name.to_owned(),
+                    dependent: package.name.to_owned(),
+                    path: package_dir,
+                });
+            } else if let Some(installed) = packages
+                .values()
+                .find(|installed| installed.package_dir == package_dir)
+            {
+                if mismatched_dirs.insert(package_dir.to_owned()) {
+                    problems.push(PackageProblem::NameMismatch {
+                        name: installed.name.to_owned(),
+                        folder_name: package_name.to_owned(),
+                        path: bsconfig::get_config_path(&package_dir).unwrap_or(package_dir),
+                    });
+                }
+            }
+        }
+    }
+
+    let dependencies = packages
+        .iter()
+        .map(|(package_name, package)| {
+            (
+                package_name,
+                AHashSet::from_iter(package.bsconfig.bs_dependencies.iter().flatten().cloned()),
+            )
+        })
+        .collect::<Vec<(&String, AHashSet<String>)>>();
+    problems.extend(
+        cycles::find(
+            dependencies
+                .iter()
+                .map(|(package_name, dependencies)| (*package_name, dependencies)),
+        )
+        .into_iter()
+        .map(|packages| PackageProblem::Cycle { packages }),
+    );
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Packages { problems })
+    }
+}
+
+/// The source folders of a package that get compiled, with whether their subfolders get compiled as
+/// well. These are the folders the watcher looks at.
+pub fn get_compiled_source_folders(package: &Package) -> Vec<(String, bool)> {
+    package
+        .source_folders
+        .iter()
+        .filter(|(dir, source)| {
+            !is_ignored_dir(package, Path::new(dir))
+                && (!source.is_type_dev() || package.is_local_dep)
+        })
+        .map(|(dir, source)| {
+            (
+                dir.to_owned(),
+                matches!(source.subdirs, Some(Subdirs::Recurse(true))),
+            )
+        })
+        .collect()
+}
+
 /// `get_source_files` is essentially a wrapper around `read_structure`, which read a
 /// list of files in a folder to a hashmap of `string` / `fs::Metadata` (file metadata). Reason for
 /// this wrapper is the recursiveness of the `bsconfig.json` subfolders. Some sources in bsconfig
@@ -314,26 +487,39 @@ fn build_package<'a>(
 /// TODO -> Check wether we actually need the `fs::Metadata`
 pub fn get_source_files(
     filter: &Option<regex::Regex>,
+    package: &Package,
     dir: &String,
     source: &PackageSource,
 ) -> AHashMap<String, SourceFileMeta> {
     let mut map: AHashMap<String, SourceFileMeta> = AHashMap::new();
 
-    let (recurse, type_) = match source {
+    let recurse = match source {
         PackageSource {
             subdirs: Some(Subdirs::Recurse(subdirs)),
-            type_,
             ..
-        } => (subdirs.to_owned(), type_),
-        PackageSource { type_, .. } => (false, type_),
+        } => subdirs.to_owned(),
+        PackageSource { .. } => false,
     };
 
     let path_dir = Path::new(dir);
-    // don't include dev sources for now
-    if type_ != &Some("dev".to_string()) {
-        match read_folders(&filter, path_dir, recurse) {
-            Ok(files) => map.extend(files),
-            Err(_e) if type_ == &Some("dev".to_string()) => {
+    let path_dir_abs = path_dir.to_lexical_absolute().unwrap();
+    if is_ignored_dir(package, path_dir) {
+        return map;
+    }
+    // the dev sources of installed packages are not compiled, they may not even be published
+    let is_type_dev = source.is_type_dev();
+    if !is_type_dev || package.is_local_dep {
+        match read_folders(filter, package, path_dir, recurse, is_type_dev) {
+            // `files` only filters the files in the folder itself, not the ones in its subdirs
+            Ok(files) => map.extend(files.into_iter().filter(|(path, _)| {
+                let path = Path::new(path);
+                match &source.files {
+                    Some(source_files) if path.parent() == Some(&path_dir_abs) => source_files
+                        .includes(path.file_name().and_then(|name| name.to_str()).unwrap()),
+                    _ => true,
+                }
+            })),
+            Err(_e) if is_type_dev => {
                 println!("Could not read folder: {dir}... Probably ok as type is dev")
             }
             Err(_e) => println!("Could not read folder: {dir}..."),
@@ -357,46 +543,71 @@ fn extend_with_children(
     filter: &Option<regex::Regex>,
     mut build: AHashMap<String, Package>,
 ) -> AHashMap<String, Package> {
-    for (_key, value) in build.iter_mut() {
-        let mut map: AHashMap<String, SourceFileMeta> = AHashMap::new();
-        value
-            .source_folders
-            .par_iter()
-            .map(|(dir, source)| get_source_files(&filter, dir, source))
-            .collect::<Vec<AHashMap<String, SourceFileMeta>>>()
-            .into_iter()
-            .for_each(|source| map.extend(source));
+    for (_key, package) in build.iter_mut() {
+        read_source_files(filter, package);
+    }
+    build
+}
 
-        let mut modules = AHashSet::from_iter(
-            map.keys()
-                .map(|key| helpers::file_path_to_module_name(key, &value.namespace)),
-        );
-        match value.namespace.to_owned() {
-            Namespace::Namespace(namespace) => {
-                let _ = modules.insert(namespace);
-            }
-            Namespace::NamespaceWithEntry {
-                namespace,
-                entry: _,
-            } => {
-                let _ = modules.insert("@".to_string() + &namespace);
-            }
-            Namespace::NoNamespace => (),
+/// Finds the source files of a package, and creates the folders for their compiler assets
+pub fn read_source_files(filter: &Option<regex::Regex>, package: &mut Package) {
+    let mut map: AHashMap<String, SourceFileMeta> = AHashMap::new();
+    package
+        .source_folders
+        .par_iter()
+        .map(|(dir, source)| get_source_files(filter, package, dir, source))
+        .collect::<Vec<AHashMap<String, SourceFileMeta>>>()
+        .into_iter()
+        .for_each(|source| map.extend(source));
+
+    let mut modules = AHashSet::from_iter(
+        map.keys()
+            .map(|key| helpers::file_path_to_module_name(key, &package.namespace)),
+    );
+    match package.namespace.to_owned() {
+        Namespace::Namespace(namespace) => {
+            let _ = modules.insert(namespace);
         }
-        value.modules = Some(modules);
-        let mut dirs = AHashSet::new();
-        map.keys().for_each(|path| {
-            let dir = std::path::Path::new(&path)
-                .strip_prefix(&value.package_dir)
-                .unwrap()
-                .parent()
-                .unwrap();
-            dirs.insert(dir.to_owned());
-        });
-        value.dirs = Some(dirs);
-        value.source_files = Some(map);
+        Namespace::NamespaceWithEntry {
+            namespace,
+            entry: _,
+        } => {
+            let _ = modules.insert("@".to_string() + &namespace);
+        }
+        Namespace::NoNamespace => (),
     }
-    build
+    package.modules = Some(modules);
+    let mut dirs = AHashSet::new();
+    map.keys().for_each(|path| {
+        let dir = std::path::Path::new(&path)
+            .strip_prefix(&package.package_dir)
+            .unwrap()
+            .parent()
+            .unwrap();
+        dirs.insert(dir.to_owned());
+    });
+    package.dirs = Some(dirs);
+    package.source_files = Some(map);
+    package.dirs.iter().flatten().for_each(|dir| {
+        let _ = std::fs::create_dir_all(Path::new(&package.get_bs_build_path()).join(dir));
+    });
+}
+
+/// The package with a changed config, with its source files read again. The config may not change
+/// the name or the dependencies of the package, that needs a new package tree.
+pub fn reload_package(
+    filter: &Option<regex::Regex>,
+    package: &Package,
+    bsconfig: bsconfig::T,
+) -> Package {
+    let mut package = make_package(
+        bsconfig,
+        &package.package_dir,
+        package.is_pinned_dep,
+        package.is_local_dep,
+    );
+    read_source_files(filter, &mut package);
+    package
 }
 
 /// Make turns a folder, that should contain a bsconfig, into a tree of Packages.
@@ -404,34 +615,298 @@ fn extend_with_children(
 /// 1. Get all the packages parsed, and take all the source folders from the bsconfig
 /// 2. Take the (by then deduplicated) packages, and find all the '.re', '.res', '.ml' and
 ///    interface files.
+///
 /// The two step process is there to reduce IO overhead
-pub fn make(filter: &Option<regex::Regex>, root_folder: &str) -> AHashMap<String, Package> {
+pub fn make(
+    filter: &Option<regex::Regex>,
+    root_folder: &str,
+) -> Result<AHashMap<String, Package>, Error> {
     /* The build_package get's called recursively. By using extend, we deduplicate all the packages
      * */
     let mut map: AHashMap<String, Package> = AHashMap::new();
 
-    let package_dir = get_package_dir("", true, root_folder);
-    let bsconfig = read_bsconfig(&package_dir);
-    build_package(&mut map, bsconfig, &package_dir, root_folder, true);
+    let bsconfig = read_bsconfig(root_folder)?;
+    build_package(&mut map, bsconfig, root_folder, true, true)?;
+    validate(&map)?;
     /* Once we have the deduplicated packages, we can add the source files for each - to minimize
      * the IO */
-    let result = extend_with_children(&filter, map);
-    result
-        .values()
-        .into_iter()
-        .for_each(|package| match &package.dirs {
-            Some(dirs) => dirs.iter().for_each(|dir| {
-                let _ = std::fs::create_dir_all(
-                    std::path::Path::new(&helpers::get_bs_build_path(root_folder, &package.name))
-                        .join(dir),
-                );
-            }),
-            None => (),
-        });
-    result
+    Ok(extend_with_children(filter, map))
+}
+
+pub fn get_package_name(path: &str) -> Result<String, Error> {
+    read_bsconfig(path).map(|bsconfig| bsconfig.name)
 }
 
-pub fn get_package_name(path: &str) -> String {
-    let bsconfig = read_bsconfig(&path);
-    bsconfig.name
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_bsconfig(dir: &Path, name: &str, dependencies: &[&str]) {
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(
+            dir.join("bsconfig.json"),
+            format!(
+                r#"{{"name": "{}", "sources": "src", "bs-dependencies": {:?}}}"#,
+                name, dependencies
+            ),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_finds_packages_like_node() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        let node_modules = root.join("node_modules");
+        let pnpm = node_modules.join(".pnpm");
+        write_bsconfig(root, "root", &["workspace", "pnpm-package"]);
+        // a workspace package, linked into the node_modules of the root, with a dependency that
+        // isn't hoisted
+        write_bsconfig(&root.join("packages/workspace"), "workspace", &["nested"]);
+        write_bsconfig(
+            &root.join("packages/workspace/node_modules/nested"),
+            "nested",
+            &[],
+        );
+        fs::create_dir_all(&node_modules).unwrap();
+        std::os::unix::fs::symlink(
+            root.join("packages/workspace"),
+            node_modules.join("workspace"),
+        )
+        .unwrap();
+        // the pnpm layout, where the dependencies of a package are linked next to it
+        write_bsconfig(
+            &pnpm.join("pnpm-package@1.0.0/node_modules/pnpm-package"),
+            "pnpm-package",
+            &["pnpm-dependency"],
+        );
+        write_bsconfig(
+            &pnpm.join("pnpm-dependency@1.0.0/node_modules/pnpm-dependency"),
+            "pnpm-dependency",
+            &[],
+        );
+        std::os::unix::fs::symlink(
+            pnpm.join("pnpm-package@1.0.0/node_modules/pnpm-package"),
+            node_modules.join("pnpm-package"),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(
+            pnpm.join("pnpm-dependency@1.0.0/node_modules/pnpm-dependency"),
+            pnpm.join("pnpm-package@1.0.0/node_modules/pnpm-dependency"),
+        )
+        .unwrap();
+
+        let packages = make(&None, root.to_str().unwrap()).unwrap();
+        let package_dir = |name: &str| packages.get(name).unwrap().package_dir.to_owned();
+        let canonical = |path: PathBuf| path.canonicalize().unwrap().to_str().unwrap().to_string();
+        assert_eq!(
+            package_dir("workspace"),
+            canonical(root.join("packages/workspace"))
+        );
+        assert_eq!(
+            package_dir("nested"),
+            canonical(root.join("packages/workspace/node_modules/nested"))
+        );
+        assert_eq!(
+            package_dir("pnpm-dependency"),
+            canonical(pnpm.join("pnpm-dependency@1.0.0/node_modules/pnpm-dependency"))
+        );
+    }
+
+    #[test]
+    fn test_reports_all_problems_with_the_packages() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        let node_modules = root.join("node_modules");
+        write_bsconfig(root, "root", &["missing", "no-config", "renamed", "a"]);
+        fs::create_dir_all(node_modules.join("no-config")).unwrap();
+        write_bsconfig(&node_modules.join("renamed"), "other-name", &[]);
+        write_bsconfig(&node_modules.join("a"), "a", &["b"]);
+        write_bsconfig(&node_modules.join("b"), "b", &["a"]);
+
+        let root_str = root.to_str().unwrap();
+        let problems = match make(&None, root_str) {
+            Err(Error::Packages { problems }) => problems,
+            result => panic!("Expected problems with the packages, got {:?}", result),
+        };
+        let package_dir = |name: &str| node_modules.join(name).to_str().unwrap().to_string();
+        assert_eq!(
+            problems,
+            vec![
+                PackageProblem::Missing {
+                    package_name: "missing".to_string(),
+                    dependent: "root".to_string(),
+                    path: package_dir("missing"),
+                },
+                PackageProblem::MissingConfig {
+                    package_name: "no-config".to_string(),
+                    dependent: "root".to_string(),
+                    path: package_dir("no-config"),
+                },
+                PackageProblem::NameMismatch {
+                    name: "other-name".to_string(),
+                    folder_name: "renamed".to_string(),
+                    path: package_dir("renamed/bsconfig.json"),
+                },
+                PackageProblem::Cycle {
+                    packages: vec!["a".to_string(), "b".to_string(), "a".to_string()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skips_ignored_dirs_and_filtered_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        for file in [
+            "src/App.res",
+            "src/App_test.res",
+            "src/Skipped.res",
+            "src/components/Button.res",
+            "src/__tests__/App_test.res",
+            "generated/Types.res",
+            "generated/Types.gen.res",
+        ] {
+            fs::create_dir_all(root.join(file).parent().unwrap()).unwrap();
+            fs::write(root.join(file), "").unwrap();
+        }
+        fs::write(
+            root.join("bsconfig.json"),
+            r#"{
+                "name": "root",
+                "ignored-dirs": ["src/__tests__"],
+                "sources": [
+                    {
+                        "dir": "src",
+                        "subdirs": true,
+                        "files": {"slow-re": "^[A-Z].*\\.res$", "excludes": ["Skipped.res"]}
+                    },
+                    {"dir": "generated", "files": ["Types.res"]}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let packages = make(&None, root.to_str().unwrap()).unwrap();
+        let mut source_files = packages
+            .get("root")
+            .unwrap()
+            .source_files
+            .as_ref()
+            .unwrap()
+            .keys()
+            .map(|path| {
+                Path::new(path)
+                    .strip_prefix(root)
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect::<Vec<String>>();
+        source_files.sort();
+        assert_eq!(
+            source_files,
+            vec![
+                "generated/Types.res",
+                "src/App.res",
+                "src/App_test.res",
+                "src/components/Button.res",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reads_dev_sources_of_local_packages() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        let installed = root.join("node_modules/installed");
+        let write_package = |dir: &Path, name: &str, dependencies: &[&str], dev: &[&str]| {
+            fs::create_dir_all(dir.join("test/unit")).unwrap();
+            fs::create_dir_all(dir.join("src")).unwrap();
+            fs::write(dir.join("src/Lib.res"), "").unwrap();
+            fs::write(dir.join("test/unit/Lib_test.res"), "").unwrap();
+            fs::write(
+                dir.join("bsconfig.json"),
+                format!(
+                    r#"{{
+                        "name": "{}",
+                        "sources": ["src", {{"dir": "test", "type": "dev", "subdirs": ["unit"]}}],
+                        "bs-dependencies": {:?},
+                        "bs-dev-dependencies": {:?}
+                    }}"#,
+                    name, dependencies, dev
+                ),
+            )
+            .unwrap();
+        };
+        write_package(root, "root", &["installed"], &["test-utils"]);
+        // the dev dependencies of an installed package don't have to be installed
+        write_package(&installed, "installed", &[], &["not-installed"]);
+        write_bsconfig(&root.join("node_modules/test-utils"), "test-utils", &[]);
+
+        let packages = make(&None, root.to_str().unwrap()).unwrap();
+        let source_files = |name: &str| {
+            let mut source_files = packages
+                .get(name)
+                .unwrap()
+                .source_files
+                .as_ref()
+                .unwrap()
+                .iter()
+                .map(|(path, meta)| {
+                    (
+                        Path::new(path)
+                            .file_name()
+                            .unwrap()
+                            .to_str()
+                            .unwrap()
+                            .to_string(),
+                        meta.is_type_dev,
+                    )
+                })
+                .collect::<Vec<(String, bool)>>();
+            source_files.sort();
+            source_files
+        };
+        assert!(packages.contains_key("test-utils"));
+        assert_eq!(
+            source_files("root"),
+            vec![
+                ("Lib.res".to_string(), false),
+                ("Lib_test.res".to_string(), true)
+            ]
+        );
+        assert_eq!(
+            source_files("installed"),
+            vec![("Lib.res".to_string(), false)]
+        );
+    }
+
+    #[test]
+    fn test_reloads_the_sources_of_a_package() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        write_bsconfig(root, "root", &[]);
+        fs::write(root.join("src/First.res"), "").unwrap();
+        let packages = make(&None, root.to_str().unwrap()).unwrap();
+        let package = packages.get("root").unwrap();
+
+        fs::remove_file(root.join("src/First.res")).unwrap();
+        fs::write(root.join("src/Second.res"), "").unwrap();
+        let mut bsconfig = package.bsconfig.to_owned();
+        bsconfig.namespace = Some(bsconfig::Namespace::Bool(true));
+        let package = reload_package(&None, package, bsconfig);
+
+        assert_eq!(package.namespace, Namespace::Namespace("Root".to_string()));
+        assert_eq!(
+            package.modules,
+            Some(AHashSet::from_iter([
+                "Second-Root".to_string(),
+                "Root".to_string()
+            ]))
+        );
+    }
 }
@@ -1,5 +1,5 @@
-/// As taken from: https://fedevitale.medium.com/thread-safe-queue-in-rust-1ed1acb9b93e
-/// https://github.com/rawnly/queue-rs
+//! As taken from: https://fedevitale.medium.com/thread-safe-queue-in-rust-1ed1acb9b93e
+//! https://github.com/rawnly/queue-rs
 
 use std::{
     collections::VecDeque,
@@ -75,8 +75,7 @@ impl<T> Queue<T> for FifoQueue<T> {
 
 #[cfg(test)]
 mod test {
-    use crate::prelude::*;
-    use crate::FifoQueue;
+    use super::*;
     use std::{sync::Arc, thread};
 
     #[test]
@@ -1,79 +1,789 @@
+use crate::bsconfig;
 use crate::build;
+use crate::build::cache::Cache;
+use crate::build::cancel::CancelToken;
+use crate::build::report::BuildReport;
+use crate::build_types::*;
+use crate::clean;
 use crate::helpers;
+use crate::package_tree;
 use crate::queue::FifoQueue;
 use crate::queue::*;
-use futures_timer::Delay;
-use notify::{Config, Error, Event, RecommendedWatcher, RecursiveMode, Watcher};
+use ahash::{AHashMap, AHashSet};
+use notify::{RecursiveMode, Watcher};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-async fn async_watch(
-    q: Arc<FifoQueue<Result<Event, Error>>>,
+/// The build that is kept in memory between changes, so a change only needs the changed modules
+/// to be parsed again, and the modules that depend on them to be compiled again.
+struct WatchState {
+    build_state: BuildState,
+    rescript_version: String,
+    /// the canonicalized paths of the source files, with the module they belong to
+    source_files: AHashMap<PathBuf, String>,
+}
+
+fn get_source_files(build_state: &BuildState) -> AHashMap<PathBuf, String> {
+    build_state
+        .modules
+        .iter()
+        .filter_map(|(module_name, module)| match &module.source_type {
+            SourceType::SourceFile(source_file) => Some(
+                std::iter::once(&source_file.implementation.path)
+                    .chain(
+                        source_file
+                            .interface
+                            .as_ref()
+                            .map(|interface| &interface.path),
+                    )
+                    .filter_map(|path| Path::new(path).canonicalize().ok())
+                    .map(|path| (path, module_name.to_owned()))
+                    .collect::<Vec<(PathBuf, String)>>(),
+            ),
+            SourceType::MlMap(_) => None,
+        })
+        .flatten()
+        .collect()
+}
+
+/// Builds from scratch, reading the package tree and the state of the previous build. The build
+/// state is kept when the sources have errors, so they can be fixed incrementally.
+fn full_build(
+    filter: &Option<regex::Regex>,
     path: &str,
+    show_progress: bool,
+    no_timing: bool,
+    cache: Option<&Cache>,
+    cancel_token: &CancelToken,
+    watches: &mut Watches,
+) -> Option<WatchState> {
+    let timing_total = Instant::now();
+    let mut build_report = BuildReport::default();
+    let (mut build_state, rescript_version, deleted_module_names) =
+        match build::initialize_build(filter, path, show_progress, no_timing, &mut build_report) {
+            Ok(result) => result,
+            Err(e) => {
+                eprintln!("{}", e);
+                watches.update(None);
+                return None;
+            }
+        };
+    // the packages are known now, changes made while compiling are picked up by the next build
+    watches.update(Some(&build_state));
+    let result = build::incremental_build(
+        &mut build_state,
+        &rescript_version,
+        &deleted_module_names,
+        show_progress,
+        no_timing,
+        false,
+        &mut build_report,
+        cache,
+        Some(cancel_token),
+    );
+    print_result(result, timing_total, show_progress, no_timing);
+    Some(WatchState {
+        source_files: get_source_files(&build_state),
+        build_state,
+        rescript_version,
+    })
+}
+
+fn print_result(
+    result: Result<(), crate::error::Error>,
+    timing_total: Instant,
+    show_progress: bool,
+    no_timing: bool,
+) {
+    match result {
+        // newer changes came in, they are built next
+        Err(crate::error::Error::Cancelled) => (),
+        Err(e) => eprintln!("{}", e),
+        Ok(()) if show_progress => {
+            let elapsed = if no_timing {
+                Duration::new(0, 0)
+            } else {
+                timing_total.elapsed()
+            };
+            println!("Done in {:.2}s", elapsed.as_secs_f64())
+        }
+        Ok(()) => (),
+    }
+}
+
+/// Marks the implementation or the interface of a module as changed, so it's parsed again, and
+/// the module and the modules that depend on it are compiled again.
+fn mark_changed(build_state: &mut BuildState, module_name: &str, path: &Path) {
+    let module = match build_state.modules.get_mut(module_name) {
+        Some(module) => module,
+        None => return,
+    };
+    if let SourceType::SourceFile(ref mut source_file) = module.source_type {
+        let is_path =
+            |source_path: &str| Path::new(source_path).canonicalize().ok().as_deref() == Some(path);
+        if is_path(&source_file.implementation.path) {
+            let implementation = &mut source_file.implementation;
+            implementation.dirty = true;
+            implementation.parse_state = ParseState::Pending;
+            implementation.parse_diagnostics = vec![];
+            implementation.hash = helpers::compute_file_hash(&implementation.path);
+        }
+        if let Some(interface) = source_file.interface.as_mut() {
+            if is_path(&interface.path) {
+                interface.dirty = true;
+                interface.parse_state = ParseState::Pending;
+                interface.parse_diagnostics = vec![];
+                interface.hash = helpers::compute_file_hash(&interface.path);
+            }
+        }
+    }
+    module.compile_dirty = true;
+    module.deps_dirty = true;
+}
+
+/// Removes a module of which the implementation is gone, with what was compiled for it. The
+/// modules that depend on it are compiled again, so they report that it's missing.
+fn remove_module(build_state: &mut BuildState, module_name: &str) {
+    let module = match build_state.modules.remove(module_name) {
+        Some(module) => module,
+        None => return,
+    };
+    build_state.module_names.remove(module_name);
+    clean::remove_module_files(build_state, &module);
+    for dep in module.deps.iter() {
+        if let Some(dep) = build_state.modules.get_mut(dep) {
+            dep.reverse_deps.remove(module_name);
+        }
+    }
+    for reverse_dep in module.reverse_deps.iter() {
+        if let Some(reverse_dep) = build_state.modules.get_mut(reverse_dep) {
+            reverse_dep.compile_dirty = true;
+            reverse_dep.deps_dirty = true;
+        }
+    }
+}
+
+/// Reads the source files of a package again, with the given config, and brings the modules of
+/// the package and its namespace in line with them. The removed modules are added to
+/// `deleted_module_names`.
+fn update_package(
+    build_state: &mut BuildState,
     filter: &Option<regex::Regex>,
-) -> notify::Result<()> {
-    loop {
-        let mut events: Vec<Event> = vec![];
-        while !q.is_empty() {
-            match q.pop() {
-                Ok(event) => events.push(event),
-                Err(_) => (),
+    package_name: &str,
+    bsconfig: bsconfig::T,
+    deleted_module_names: &mut AHashSet<String>,
+) -> Result<(), crate::error::Error> {
+    let package = match build_state.packages.get(package_name) {
+        Some(package) => package_tree::reload_package(filter, package, bsconfig),
+        None => return Ok(()),
+    };
+    let source_files = package.source_files.to_owned().unwrap_or_default();
+
+    let package_modules = build_state
+        .modules
+        .iter()
+        .filter(|(_, module)| module.package_name == package.name)
+        .filter_map(|(module_name, module)| match &module.source_type {
+            SourceType::SourceFile(source_file) => Some((
+                module_name.to_owned(),
+                source_file.implementation.path.to_owned(),
+                source_file
+                    .interface
+                    .as_ref()
+                    .map(|interface| interface.path.to_owned()),
+            )),
+            SourceType::MlMap(_) => None,
+        })
+        .collect::<Vec<(String, String, Option<String>)>>();
+
+    let mut known_files = AHashSet::new();
+    for (module_name, implementation, interface) in package_modules {
+        if !source_files.contains_key(&implementation) {
+            remove_module(build_state, &module_name);
+            deleted_module_names.insert(module_name);
+            continue;
+        }
+        known_files.insert(implementation);
+        match interface {
+            Some(interface) if !source_files.contains_key(&interface) => {
+                let module = build_state.modules.get_mut(&module_name).unwrap();
+                if let SourceType::SourceFile(ref mut source_file) = module.source_type {
+                    source_file.interface = None;
+                }
+                module.compile_dirty = true;
+                module.deps_dirty = true;
+            }
+            Some(interface) => {
+                known_files.insert(interface);
             }
+            None => (),
         }
+    }
 
-        let needs_compile = events.iter().any(|event| {
-            event.paths.iter().any(|path| {
-                let path_buf = path.to_path_buf();
-                let name = path_buf
-                    .file_name()
-                    .and_then(|x| x.to_str())
-                    .unwrap_or("Unknown")
-                    .to_string();
-
-                let extension = path_buf.extension().and_then(|ext| ext.to_str());
-                match extension {
-                    Some(extension) => {
-                        (helpers::is_implementation_file(&extension)
-                            || helpers::is_interface_file(&extension))
-                            && filter
-                                .as_ref()
-                                .map(|re| !re.is_match(&name))
-                                .unwrap_or(true)
-                    }
+    if let Some(package_modules) = package.modules.to_owned() {
+        build_state.module_names.extend(package_modules)
+    }
+    let mut new_files = source_files
+        .iter()
+        .filter(|(file, _)| !known_files.contains(*file))
+        .collect::<Vec<(&String, &package_tree::SourceFileMeta)>>();
+    new_files.sort_by_key(|(file, _)| *file);
+    for (file, metadata) in new_files.iter() {
+        build::insert_source_file(build_state, &package, file, metadata)?;
+    }
+    // modules that failed before might have used one of the new modules
+    if !new_files.is_empty() {
+        for module in build_state.modules.values_mut() {
+            if module.compile_dirty {
+                module.deps_dirty = true;
+            }
+        }
+    }
+
+    // the namespace depends on all the modules of the package
+    if let Some(namespace) = package.namespace.to_suffix() {
+        let reverse_deps = build_state
+            .modules
+            .get(&namespace)
+            .map(|module| module.reverse_deps.to_owned())
+            .unwrap_or_default();
+        build::insert_mlmap(build_state, &package);
+        if let Some(module) = build_state.modules.get_mut(&namespace) {
+            module.source_type = SourceType::MlMap(MlMap { dirty: true });
+            module.reverse_deps = reverse_deps;
+        }
+    }
+
+    build_state
+        .packages
+        .insert(package.name.to_owned(), package);
+    Ok(())
+}
+
+/// Applies a change to the config of a package. A change to the root config, which applies to all
+/// packages, or a change to the dependencies, the name or the namespace of a package, needs a build
+/// from scratch, so this returns `None`.
+fn update_config(
+    build_state: &mut BuildState,
+    filter: &Option<regex::Regex>,
+    package_name: &str,
+    deleted_module_names: &mut AHashSet<String>,
+) -> Option<()> {
+    if package_name == build_state.root_config_name {
+        return None;
+    }
+    let package = build_state.packages.get(package_name)?;
+    let bsconfig = bsconfig::read_package_config(&package.package_dir).ok()?;
+    let previous = &package.bsconfig;
+    if bsconfig.name != previous.name
+        || bsconfig.bs_dependencies != previous.bs_dependencies
+        || bsconfig.bs_dev_dependencies != previous.bs_dev_dependencies
+        || bsconfig.pinned_dependencies != previous.pinned_dependencies
+        || package_tree::get_namespace(&bsconfig) != package.namespace
+    {
+        return None;
+    }
 
-                    _ => false,
+    update_package(
+        build_state,
+        filter,
+        package_name,
+        bsconfig,
+        deleted_module_names,
+    )
+    .ok()?;
+
+    // the flags of the compiler might have changed, so everything in the package is built again
+    for module in build_state
+        .modules
+        .values_mut()
+        .filter(|module| module.package_name == package_name)
+    {
+        if let SourceType::SourceFile(ref mut source_file) = module.source_type {
+            source_file.implementation.dirty = true;
+            source_file.implementation.parse_state = ParseState::Pending;
+            if let Some(interface) = source_file.interface.as_mut() {
+                interface.dirty = true;
+                interface.parse_state = ParseState::Pending;
+            }
+        }
+        module.compile_dirty = true;
+        module.deps_dirty = true;
+    }
+    Some(())
+}
+
+/// The package that has its config at the given path.
+fn get_config_package(build_state: &BuildState, path: &Path) -> Option<String> {
+    let dir = path.parent()?;
+    build_state
+        .packages
+        .values()
+        .find(|package| {
+            Path::new(&package.package_dir)
+                .canonicalize()
+                .ok()
+                .as_deref()
+                == Some(dir)
+        })
+        .map(|package| package.name.to_owned())
+}
+
+/// The package of the innermost package dir that contains the given path.
+fn get_path_package(build_state: &BuildState, path: &Path) -> Option<String> {
+    build_state
+        .packages
+        .values()
+        .filter_map(|package| {
+            Path::new(&package.package_dir)
+                .canonicalize()
+                .ok()
+                .filter(|package_dir| path.starts_with(package_dir))
+                .map(|package_dir| (package_dir, package))
+        })
+        .max_by_key(|(package_dir, _)| package_dir.components().count())
+        .map(|(_, package)| package.name.to_owned())
+}
+
+/// Brings the build state in line with the changed files: changed sources are parsed again, and
+/// the sources of a package are read again when files were added or removed, or when its config
+/// changed. Returns the modules that were removed, or `None` when the changes need a build from
+/// scratch.
+fn apply_changes(
+    watch_state: &mut WatchState,
+    filter: &Option<regex::Regex>,
+    changed_paths: &AHashSet<PathBuf>,
+) -> Option<AHashSet<String>> {
+    let build_state = &mut watch_state.build_state;
+    let mut changed_configs = AHashSet::new();
+    let mut changed_packages = AHashSet::new();
+    let mut changed_modules = vec![];
+    for path in changed_paths.iter() {
+        // removed files can't be canonicalized, but their folder can
+        let path = PathBuf::from(helpers::canonicalize_parent_string_path(path.to_str()?)?);
+        let is_config = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(bsconfig::is_config_file)
+            .unwrap_or(false);
+        if is_config {
+            changed_configs.insert(get_config_package(build_state, &path)?);
+            continue;
+        }
+        match watch_state.source_files.get(&path) {
+            Some(module_name) if path.exists() => changed_modules.push((module_name, path)),
+            Some(module_name) => {
+                let module = build_state.get_module(module_name)?;
+                changed_packages.insert(module.package_name.to_owned());
+            }
+            None => {
+                if let Some(package_name) = get_path_package(build_state, &path) {
+                    changed_packages.insert(package_name);
                 }
-            })
-        });
+            }
+        }
+    }
+
+    let mut deleted_module_names = AHashSet::new();
+    for package_name in changed_configs.iter() {
+        update_config(build_state, filter, package_name, &mut deleted_module_names)?;
+    }
+    for package_name in changed_packages.difference(&changed_configs) {
+        let bsconfig = build_state.get_package(package_name)?.bsconfig.to_owned();
+        update_package(
+            build_state,
+            filter,
+            package_name,
+            bsconfig,
+            &mut deleted_module_names,
+        )
+        .ok()?;
+    }
+    for (module_name, path) in changed_modules.iter() {
+        mark_changed(build_state, module_name, path);
+    }
+    watch_state.source_files = get_source_files(build_state);
+    Some(deleted_module_names)
+}
+
+fn is_relevant(path: &Path, filter: &Option<regex::Regex>) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|x| x.to_str())
+        .unwrap_or("Unknown")
+        .to_string();
+
+    // a change to the config of a package can change what needs to be compiled
+    if bsconfig::is_config_file(&name) {
+        return true;
+    }
+
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    match extension {
+        Some(extension) => {
+            (helpers::is_implementation_file(extension) || helpers::is_interface_file(extension))
+                && filter
+                    .as_ref()
+                    .map(|re| !re.is_match(&name))
+                    .unwrap_or(true)
+        }
+
+        _ => false,
+    }
+}
 
-        if needs_compile {
-            // Wait for events to settle
-            Delay::new(Duration::from_millis(300)).await;
+/// The paths of a batch of debounced events that can change the build.
+fn get_changed_paths(events: DebounceEventResult, filter: &Option<regex::Regex>) -> Vec<PathBuf> {
+    match events {
+        Ok(events) => events
+            .into_iter()
+            .map(|event| event.path)
+            .filter(|path| is_relevant(path, filter))
+            .collect(),
+        Err(errors) => {
+            errors
+                .iter()
+                .for_each(|error| log::error!("Could not watch: {}", error));
+            vec![]
+        }
+    }
+}
 
-            // Flush any remaining events that came in before
-            while !q.is_empty() {
-                let _ = q.pop();
+/// The folders to watch: the folders of the packages, non-recursively, for their configs, and the
+/// source folders. Build output, VCS folders and the rest of `node_modules` are left out. Without a
+/// build state, we don't know the packages, so the whole project is watched.
+fn get_watch_dirs(
+    build_state: Option<&BuildState>,
+    folder: &str,
+) -> AHashMap<PathBuf, RecursiveMode> {
+    let build_state = match build_state {
+        Some(build_state) => build_state,
+        None => return AHashMap::from([(PathBuf::from(folder), RecursiveMode::Recursive)]),
+    };
+    let mut dirs = AHashMap::new();
+    for package in build_state.packages.values() {
+        dirs.entry(PathBuf::from(&package.package_dir))
+            .or_insert(RecursiveMode::NonRecursive);
+        for (dir, recursive) in package_tree::get_compiled_source_folders(package) {
+            if recursive {
+                dirs.insert(PathBuf::from(dir), RecursiveMode::Recursive);
+            } else {
+                dirs.entry(PathBuf::from(dir))
+                    .or_insert(RecursiveMode::NonRecursive);
             }
+        }
+    }
+    dirs
+}
 
-            let _ = build::build(filter, path);
+/// The folders the watcher is watching, kept in line with the packages of the build.
+struct Watches<'a> {
+    watcher: &'a mut dyn Watcher,
+    folder: String,
+    dirs: AHashMap<PathBuf, RecursiveMode>,
+}
+
+impl Watches<'_> {
+    fn update(&mut self, build_state: Option<&BuildState>) {
+        let dirs = get_watch_dirs(build_state, &self.folder);
+        for (dir, mode) in self.dirs.iter() {
+            if dirs.get(dir) != Some(mode) {
+                let _ = self.watcher.unwatch(dir);
+            }
         }
+        self.dirs = dirs
+            .into_iter()
+            .filter(|(dir, mode)| {
+                self.dirs.get(dir) == Some(mode)
+                    || match self.watcher.watch(dir, *mode) {
+                        Ok(()) => true,
+                        // a source folder that doesn't exist (yet)
+                        Err(e) => {
+                            log::debug!("Could not watch {}: {}", dir.display(), e);
+                            false
+                        }
+                    }
+            })
+            .collect();
     }
 }
 
-pub fn start(filter: &Option<regex::Regex>, folder: &str) {
-    futures::executor::block_on(async {
-        let queue = Arc::new(FifoQueue::<Result<Event, Error>>::new());
-        let producer = queue.clone();
-        let consumer = queue.clone();
+#[allow(clippy::too_many_arguments)]
+fn watch(
+    q: Arc<FifoQueue<Vec<PathBuf>>>,
+    watcher: &mut dyn Watcher,
+    path: &str,
+    filter: &Option<regex::Regex>,
+    show_progress: bool,
+    no_timing: bool,
+    cache: Option<&Cache>,
+    cancel_token: CancelToken,
+) {
+    let mut watches = Watches {
+        watcher,
+        folder: path.to_string(),
+        dirs: AHashMap::new(),
+    };
+    // errors in the initial build are reported, but we keep watching, so they can be fixed
+    let mut watch_state = full_build(
+        filter,
+        path,
+        show_progress,
+        no_timing,
+        cache,
+        &cancel_token,
+        &mut watches,
+    );
 
-        let mut watcher = RecommendedWatcher::new(move |res| producer.push(res), Config::default())
-            .expect("Could not create watcher");
-        watcher
-            .watch(folder.as_ref(), RecursiveMode::Recursive)
-            .expect("Could not start watcher");
+    loop {
+        // waits until the debouncer reports a change
+        let mut changed_paths = AHashSet::from_iter(q.pop());
+        // reset before taking the other changes, so a change that comes in while they are taken
+        // either is part of this build, or cancels it
+        cancel_token.reset();
+        // the changes that came in while the previous build was running are built together
+        while !q.is_empty() {
+            changed_paths.extend(q.pop());
+        }
 
-        if let Err(e) = async_watch(consumer, folder, filter).await {
-            println!("error: {:?}", e)
+        let timing_total = Instant::now();
+        let deleted_module_names = watch_state
+            .as_mut()
+            .and_then(|watch_state| apply_changes(watch_state, filter, &changed_paths));
+        match (watch_state.as_mut(), deleted_module_names) {
+            (Some(watch_state), Some(deleted_module_names)) => {
+                // a config can change the source folders of its package
+                watches.update(Some(&watch_state.build_state));
+                let result = build::incremental_build(
+                    &mut watch_state.build_state,
+                    &watch_state.rescript_version,
+                    &deleted_module_names,
+                    show_progress,
+                    no_timing,
+                    false,
+                    &mut BuildReport::default(),
+                    cache,
+                    Some(&cancel_token),
+                );
+                print_result(result, timing_total, show_progress, no_timing);
+            }
+            _ => {
+                watch_state = full_build(
+                    filter,
+                    path,
+                    show_progress,
+                    no_timing,
+                    cache,
+                    &cancel_token,
+                    &mut watches,
+                );
+            }
+        }
+    }
+}
+
+/// Builds the project, and builds it again whenever files change. Changes are reported after no
+/// file changed for the `debounce` duration, so a burst of changes, like a checkout, is built once.
+pub fn start(
+    filter: &Option<regex::Regex>,
+    folder: &str,
+    show_progress: bool,
+    no_timing: bool,
+    debounce: Duration,
+    cache: Option<&Cache>,
+) {
+    let queue = Arc::new(FifoQueue::<Vec<PathBuf>>::new());
+    let producer = queue.clone();
+    let cancel_token = CancelToken::new();
+    let watcher_cancel_token = cancel_token.clone();
+    let watcher_filter = filter.to_owned();
+
+    let mut debouncer = new_debouncer(debounce, None, move |events: DebounceEventResult| {
+        let changed_paths = get_changed_paths(events, &watcher_filter);
+        if !changed_paths.is_empty() {
+            // a relevant change makes the build that is running outdated
+            watcher_cancel_token.cancel();
+            producer.push(changed_paths)
         }
     })
+    .expect("Could not create watcher");
+    watch(
+        queue,
+        debouncer.watcher(),
+        folder,
+        filter,
+        show_progress,
+        no_timing,
+        cache,
+        cancel_token,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_watches_the_source_folders_and_configs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        let dependency = root.join("node_modules/dependency");
+        fs::create_dir_all(root.join("src/nested")).unwrap();
+        fs::create_dir_all(dependency.join("src")).unwrap();
+        fs::create_dir_all(dependency.join("test")).unwrap();
+        fs::write(
+            root.join("bsconfig.json"),
+            r#"{"name": "root", "sources": {"dir": "src", "subdirs": true}, "bs-dependencies": ["dependency"]}"#,
+        )
+        .unwrap();
+        fs::write(
+            dependency.join("bsconfig.json"),
+            r#"{"name": "dependency", "sources": ["src", {"dir": "test", "type": "dev"}]}"#,
+        )
+        .unwrap();
+
+        let packages = package_tree::make(&None, root.to_str().unwrap()).unwrap();
+        let root_dir = packages.get("root").unwrap().package_dir.to_owned();
+        let dependency_dir = packages.get("dependency").unwrap().package_dir.to_owned();
+        let build_state = BuildState::new(root_dir.to_owned(), "root".to_string(), packages);
+
+        // the dev sources of an installed package are not compiled, so they are not watched
+        assert_eq!(
+            get_watch_dirs(Some(&build_state), &root_dir),
+            AHashMap::from([
+                (PathBuf::from(&root_dir), RecursiveMode::NonRecursive),
+                (
+                    PathBuf::from(format!("{}/src", root_dir)),
+                    RecursiveMode::Recursive
+                ),
+                (PathBuf::from(&dependency_dir), RecursiveMode::NonRecursive),
+                (
+                    PathBuf::from(format!("{}/src", dependency_dir)),
+                    RecursiveMode::NonRecursive
+                ),
+            ])
+        );
+        assert_eq!(
+            get_watch_dirs(None, &root_dir),
+            AHashMap::from([(PathBuf::from(&root_dir), RecursiveMode::Recursive)])
+        );
+    }
+
+    #[test]
+    fn test_builds_the_modules_with_warnings_again() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(
+            root.join("bsconfig.json"),
+            r#"{"name": "root", "sources": "src"}"#,
+        )
+        .unwrap();
+        fs::write(root.join("src/Warned.res"), "let x = 1").unwrap();
+        fs::write(root.join("src/Clean.res"), "let y = 2").unwrap();
+
+        let packages = package_tree::make(&None, root.to_str().unwrap()).unwrap();
+        let root_dir = packages.get("root").unwrap().package_dir.to_owned();
+        let mut build_state = BuildState::new(root_dir, "root".to_string(), packages);
+        build::parse_packages(&mut build_state).unwrap();
+
+        // the state after a build in which Warned had parse and compile warnings
+        let package = build_state.get_package("root").unwrap().to_owned();
+        let mut assets = vec![];
+        for (module_name, module) in build_state.modules.iter_mut() {
+            module.compile_dirty = false;
+            if let SourceType::SourceFile(ref mut source_file) = module.source_type {
+                let implementation = &mut source_file.implementation;
+                implementation.dirty = false;
+                (implementation.parse_state, implementation.compile_state) =
+                    if module_name == "Warned" {
+                        (ParseState::Warning, CompileState::Warning)
+                    } else {
+                        (ParseState::Success, CompileState::Success)
+                    };
+                for extension in ["ast", "cmj"] {
+                    let asset = helpers::get_compiler_asset(
+                        &implementation.path,
+                        &package,
+                        &package.namespace,
+                        extension,
+                    );
+                    fs::create_dir_all(Path::new(&asset).parent().unwrap()).unwrap();
+                    fs::write(&asset, "").unwrap();
+                    assets.push((module_name.to_owned(), asset));
+                }
+            }
+        }
+
+        clean::cleanup_after_build(&mut build_state);
+
+        // the assets of Warned are gone, so the next build parses and compiles it again
+        for (module_name, asset) in assets {
+            assert_eq!(
+                Path::new(&asset).exists(),
+                module_name != "Warned",
+                "{}",
+                asset
+            );
+        }
+        let is_dirty = |module_name: &str| {
+            let module = build_state.get_module(module_name).unwrap();
+            match &module.source_type {
+                SourceType::SourceFile(source_file) => {
+                    (source_file.implementation.dirty, module.compile_dirty)
+                }
+                SourceType::MlMap(_) => panic!("{} is not a source file", module_name),
+            }
+        };
+        assert_eq!(is_dirty("Warned"), (true, true));
+        assert_eq!(is_dirty("Clean"), (false, false));
+    }
+
+    #[test]
+    fn test_compiles_a_module_again_when_it_gets_an_interface() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(
+            root.join("bsconfig.json"),
+            r#"{"name": "root", "sources": "src"}"#,
+        )
+        .unwrap();
+        fs::write(root.join("src/App.res"), "let x = 1").unwrap();
+
+        let packages = package_tree::make(&None, root.to_str().unwrap()).unwrap();
+        let root_dir = packages.get("root").unwrap().package_dir.to_owned();
+        let mut build_state = BuildState::new(root_dir, "root".to_string(), packages);
+        build::parse_packages(&mut build_state).unwrap();
+        // the state after a build of App
+        let module = build_state.modules.get_mut("App").unwrap();
+        module.compile_dirty = false;
+        module.deps_dirty = false;
+        if let SourceType::SourceFile(ref mut source_file) = module.source_type {
+            source_file.implementation.dirty = false;
+        }
+        let mut watch_state = WatchState {
+            source_files: get_source_files(&build_state),
+            build_state,
+            rescript_version: "11.0.0".to_string(),
+        };
+
+        fs::write(root.join("src/App.resi"), "let x: int").unwrap();
+        let changed_paths = AHashSet::from([root.join("src/App.resi").canonicalize().unwrap()]);
+        apply_changes(&mut watch_state, &None, &changed_paths).unwrap();
+
+        let module = watch_state.build_state.get_module("App").unwrap();
+        assert!(module.compile_dirty);
+        assert!(module.deps_dirty);
+        match &module.source_type {
+            SourceType::SourceFile(SourceFile {
+                interface: Some(interface),
+                ..
+            }) => assert!(interface.dirty),
+            source_type => panic!("App has no interface: {:?}", source_type),
+        }
+    }
 }